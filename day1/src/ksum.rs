@@ -0,0 +1,631 @@
+use super::term::{widen, Number, Term, Widened};
+use std::collections::HashMap;
+
+// Finding k distinct entries that add up to a sum. Every strategy returns
+// the indices of the terms in ascending order, and never uses an entry twice.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    // Fixes all but two terms and looks up the last one in a hash map,
+    // O(n^(k-1)).
+    HashSet,
+    // Fixes all but two terms and walks the sorted terms from both ends,
+    // O(n log n + n^(k-1)).
+    TwoPointer,
+    // Combines every sum of k/2 terms with every sum of the remaining terms,
+    // O(n^ceil(k/2)) time and O(n^floor(k/2)) memory.
+    MeetInTheMiddle,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [
+        Strategy::HashSet,
+        Strategy::TwoPointer,
+        Strategy::MeetInTheMiddle,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::HashSet => "hash-set",
+            Strategy::TwoPointer => "two-pointer",
+            Strategy::MeetInTheMiddle => "meet-in-the-middle",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Strategy> {
+        Strategy::ALL
+            .iter()
+            .copied()
+            .find(|strategy| strategy.name() == name)
+    }
+}
+
+pub fn find_terms<T: Term>(
+    terms: &[T],
+    k: usize,
+    sum: &T,
+    strategy: Strategy,
+) -> Option<Vec<usize>> {
+    if k > terms.len() {
+        return None;
+    }
+    let mut indices = match widen(terms, sum) {
+        Widened::Small(values, sum) => find_widened(&values, k, sum, strategy),
+        Widened::Big(values, sum) => find_widened(&values, k, sum, strategy),
+    }?;
+    indices.sort_unstable();
+    Some(indices)
+}
+
+fn find_widened<N: Number>(
+    values: &[N],
+    k: usize,
+    sum: N,
+    strategy: Strategy,
+) -> Option<Vec<usize>> {
+    match strategy {
+        Strategy::HashSet => {
+            let order: Vec<usize> = (0..values.len()).collect();
+            fix_terms(values, &order, k, sum, &find_pair_hashed)
+        }
+        Strategy::TwoPointer => {
+            let order = sorted_order(values);
+            let sorted: Vec<N> = order.iter().map(|i| values[*i].clone()).collect();
+            fix_terms(&sorted, &order, k, sum, &find_pair_sorted)
+        }
+        Strategy::MeetInTheMiddle => meet_in_the_middle(values, k, sum),
+    }
+}
+
+fn sorted_order<N: Number>(values: &[N]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|i1, i2| values[*i1].cmp(&values[*i2]));
+    order
+}
+
+// Positions in values of two entries after start that add up to sum.
+type FindPair<N> = dyn Fn(&[N], usize, N) -> Option<(usize, usize)>;
+
+// Picks terms in order until only two are left for find_pair. Returns
+// indices into terms, order maps positions in values to them.
+fn fix_terms<N: Number>(
+    values: &[N],
+    order: &[usize],
+    k: usize,
+    sum: N,
+    find_pair: &FindPair<N>,
+) -> Option<Vec<usize>> {
+    fn fix<N: Number>(
+        values: &[N],
+        start: usize,
+        k: usize,
+        sum: N,
+        find_pair: &FindPair<N>,
+    ) -> Option<Vec<usize>> {
+        match k {
+            0 => {
+                if sum.is_zero() {
+                    Some(Vec::new())
+                } else {
+                    None
+                }
+            }
+            1 => (start..values.len())
+                .find(|i| values[*i] == sum)
+                .map(|i| vec![i]),
+            2 => find_pair(values, start, sum).map(|(i1, i2)| vec![i1, i2]),
+            _ => (start..values.len()).find_map(|i| {
+                let rest_sum = sum.clone() - values[i].clone();
+                let mut rest = fix(values, i + 1, k - 1, rest_sum, find_pair)?;
+                rest.push(i);
+                Some(rest)
+            }),
+        }
+    }
+    let positions = fix(values, 0, k, sum, find_pair)?;
+    Some(positions.iter().map(|position| order[*position]).collect())
+}
+
+fn find_pair_hashed<N: Number>(values: &[N], start: usize, sum: N) -> Option<(usize, usize)> {
+    let mut seen: HashMap<&N, usize> = HashMap::new();
+    for (i2, value) in values.iter().enumerate().skip(start) {
+        if let Some(i1) = seen.get(&(sum.clone() - value.clone())) {
+            return Some((*i1, i2));
+        }
+        seen.entry(value).or_insert(i2);
+    }
+    None
+}
+
+fn find_pair_sorted<N: Number>(values: &[N], start: usize, sum: N) -> Option<(usize, usize)> {
+    if values.len() < start + 2 {
+        return None;
+    }
+    let (mut low, mut high) = (start, values.len() - 1);
+    while low < high {
+        let pair = values[low].clone() + values[high].clone();
+        if pair == sum {
+            return Some((low, high));
+        } else if pair < sum {
+            low += 1;
+        } else {
+            high -= 1;
+        }
+    }
+    None
+}
+
+// Calls f with every k-combination of 0..n in lexicographic order, until it
+// returns true.
+fn for_each_combination<F>(n: usize, k: usize, mut f: F) -> bool
+where
+    F: FnMut(&[usize]) -> bool,
+{
+    if k > n {
+        return false;
+    }
+    let mut combination: Vec<usize> = (0..k).collect();
+    loop {
+        if f(&combination) {
+            return true;
+        }
+        let i = match (0..k).rev().find(|i| combination[*i] != i + n - k) {
+            Some(i) => i,
+            None => return false,
+        };
+        combination[i] += 1;
+        for j in i + 1..k {
+            combination[j] = combination[j - 1] + 1;
+        }
+    }
+}
+
+// Every solution splits into its k/2 smallest indices and the rest. For each
+// sum of the first half only the combination with the smallest last index is
+// kept: any second half that fits behind another combination with the same
+// sum fits behind that one too.
+fn meet_in_the_middle<N: Number>(values: &[N], k: usize, sum: N) -> Option<Vec<usize>> {
+    let total = |combination: &[usize]| -> N {
+        combination
+            .iter()
+            .fold(N::zero(), |total, i| total + values[*i].clone())
+    };
+    let first = k / 2;
+    let mut firsts: HashMap<N, Vec<usize>> = HashMap::new();
+    for_each_combination(values.len(), first, |combination| {
+        let kept = firsts
+            .entry(total(combination))
+            .or_insert_with(|| combination.to_vec());
+        if combination.last() < kept.last() {
+            *kept = combination.to_vec();
+        }
+        false
+    });
+
+    let mut found: Option<Vec<usize>> = None;
+    for_each_combination(values.len(), k - first, |second| {
+        let matching = match firsts.get(&(sum.clone() - total(second))) {
+            Some(matching) => matching,
+            None => return false,
+        };
+        let fits = match (matching.last(), second.first()) {
+            (Some(last), Some(next)) => last < next,
+            _ => true,
+        };
+        if fits {
+            found = Some(matching.iter().chain(second.iter()).copied().collect());
+        }
+        fits
+    });
+    found
+}
+
+// Every distinct combination of k terms adding up to sum, in ascending
+// order. Terms with the same value are interchangeable, so each combination
+// of values comes up once however many ways it can be picked.
+pub struct Combinations<T: Term> {
+    // The terms in ascending order.
+    sorted: Vec<T>,
+    positions: Box<dyn Iterator<Item = Vec<usize>>>,
+}
+
+pub fn combinations<T: Term>(terms: &[T], k: usize, sum: &T) -> Combinations<T> {
+    fn start<N: Number>(values: Vec<N>, k: usize, sum: N) -> (Vec<usize>, Positions<N>) {
+        let order = sorted_order(&values);
+        let values: Vec<N> = order.iter().map(|i| values[*i].clone()).collect();
+        let mut prefix: Vec<N> = vec![N::zero()];
+        for value in values.iter() {
+            prefix.push(prefix.last().unwrap().clone() + value.clone());
+        }
+        let positions = Positions {
+            values,
+            prefix,
+            k,
+            sum,
+            chosen: Vec::new(),
+            total: N::zero(),
+            next: 0,
+            done: false,
+        };
+        (order, positions)
+    }
+
+    let (order, positions): (Vec<usize>, Box<dyn Iterator<Item = Vec<usize>>>) =
+        match widen(terms, sum) {
+            Widened::Small(values, sum) => {
+                let (order, positions) = start(values, k, sum);
+                (order, Box::new(positions))
+            }
+            Widened::Big(values, sum) => {
+                let (order, positions) = start(values, k, sum);
+                (order, Box::new(positions))
+            }
+        };
+    Combinations {
+        sorted: order.iter().map(|i| terms[*i].clone()).collect(),
+        positions,
+    }
+}
+
+impl<T: Term> Iterator for Combinations<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        let positions = self.positions.next()?;
+        Some(positions.iter().map(|i| self.sorted[*i].clone()).collect())
+    }
+}
+
+// Positions of the combinations in the sorted values.
+struct Positions<N: Number> {
+    values: Vec<N>,
+    // prefix[i] is the sum of the i smallest values.
+    prefix: Vec<N>,
+    k: usize,
+    sum: N,
+    chosen: Vec<usize>,
+    total: N,
+    next: usize,
+    done: bool,
+}
+
+impl<N: Number> Positions<N> {
+    // Drops the last choice and moves past every value equal to it.
+    fn backtrack(&mut self) -> bool {
+        let last = match self.chosen.pop() {
+            Some(last) => last,
+            None => return false,
+        };
+        self.total = self.total.clone() - self.values[last].clone();
+        self.next = last + 1;
+        while self.next < self.values.len() && self.values[self.next] == self.values[last] {
+            self.next += 1;
+        }
+        true
+    }
+
+    // Whether the remaining terms could still add up to sum, using the
+    // smallest and the largest values that are left.
+    fn reachable(&self, remaining: usize) -> bool {
+        let n = self.values.len();
+        if self.next + remaining > n {
+            return false;
+        }
+        let lowest = self.prefix[self.next + remaining].clone() - self.prefix[self.next].clone();
+        let highest = self.prefix[n].clone() - self.prefix[n - remaining].clone();
+        self.total.clone() + lowest <= self.sum && self.total.clone() + highest >= self.sum
+    }
+}
+
+impl<N: Number> Iterator for Positions<N> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        while !self.done {
+            if self.chosen.len() == self.k {
+                let found = if self.total == self.sum {
+                    Some(self.chosen.clone())
+                } else {
+                    None
+                };
+                self.done = !self.backtrack();
+                if found.is_some() {
+                    return found;
+                }
+            } else if self.reachable(self.k - self.chosen.len()) {
+                self.chosen.push(self.next);
+                self.total = self.total.clone() + self.values[self.next].clone();
+                self.next += 1;
+            } else {
+                self.done = !self.backtrack();
+            }
+        }
+        None
+    }
+}
+
+// The most counts count_subsets keeps in its table, one per size and
+// partial sum.
+const MAX_COUNT_CELLS: usize = 1 << 24;
+
+// The number of subsets of the entries that add up to sum. Unlike
+// combinations, entries with the same value are told apart. None when the
+// table of counts would be too large, or the count does not fit in u128.
+pub fn count_subsets<T: Term>(terms: &[T], sum: &T) -> Option<u128> {
+    count_table(terms, None, sum)?[0]
+}
+
+// Like count_subsets, but only subsets of k entries.
+pub fn count_subsets_of_size<T: Term>(terms: &[T], k: usize, sum: &T) -> Option<u128> {
+    if k > terms.len() {
+        return Some(0);
+    }
+    count_table(terms, Some(k), sum)?[k]
+}
+
+fn count_table<T: Term>(terms: &[T], k: Option<usize>, sum: &T) -> Option<Vec<Option<u128>>> {
+    match widen(terms, sum) {
+        Widened::Small(values, sum) => count_widened(&values, k, sum),
+        Widened::Big(values, sum) => count_widened(&values, k, sum),
+    }
+}
+
+// For every size up to k, or for all sizes together when k is None, the
+// number of subsets adding up to sum.
+fn count_widened<N: Number>(values: &[N], k: Option<usize>, sum: N) -> Option<Vec<Option<u128>>> {
+    let sizes = k.map(|k| k + 1).unwrap_or(1);
+    let (mut negative, mut positive) = (N::zero(), N::zero());
+    for value in values.iter() {
+        if *value < N::zero() {
+            negative = negative + value.clone();
+        } else {
+            positive = positive + value.clone();
+        }
+    }
+    if sum < negative || sum > positive {
+        return Some(vec![Some(0); sizes]);
+    }
+    // Partial sums that the rest of the terms cannot bring back to sum are
+    // not worth counting.
+    let lowest = negative.clone().max(sum.clone() - positive.clone());
+    let highest = positive.min(sum.clone() - negative);
+    let range = match (highest - lowest.clone()).to_i64() {
+        Some(span) if (span as u128 + 1) * sizes as u128 <= MAX_COUNT_CELLS as u128 => span + 1,
+        _ => return None,
+    };
+    // Where a sum ends up in the table, if it fits.
+    let offset_of = |value: N| -> Option<i64> {
+        (value - lowest.clone())
+            .to_i64()
+            .filter(|offset| (0..range).contains(offset))
+    };
+
+    // counts[size][s - lowest] is the number of subsets of the terms so far
+    // adding up to s, None once it overflowed.
+    let mut counts: Vec<Vec<Option<u128>>> = vec![vec![Some(0); range as usize]; sizes];
+    if let Some(empty) = offset_of(N::zero()) {
+        counts[0][empty as usize] = Some(1);
+    }
+    for value in values.iter() {
+        // Terms beyond the width of the table never end up in it.
+        let term = match value.to_i64() {
+            Some(term) if term.unsigned_abs() < range as u64 => term,
+            _ => continue,
+        };
+        for size in (0..sizes).rev() {
+            let from = match k {
+                Some(_) if size == 0 => continue,
+                Some(_) => size - 1,
+                None => size,
+            };
+            if from == size && term == 0 {
+                // Every subset can take the zero or leave it.
+                for count in counts[size].iter_mut() {
+                    *count = count.and_then(|count| count.checked_mul(2));
+                }
+                continue;
+            }
+            // Walking away from the term reads every count before the term
+            // is added to it.
+            for step in 0..range {
+                let offset = if term > 0 { range - 1 - step } else { step };
+                let source = offset - term;
+                if source < 0 || source >= range {
+                    continue;
+                }
+                let added = counts[from][source as usize];
+                let count = &mut counts[size][offset as usize];
+                *count = match (*count, added) {
+                    (Some(count), Some(added)) => count.checked_add(added),
+                    _ => None,
+                };
+            }
+        }
+    }
+    let at_sum = offset_of(sum)? as usize;
+    Some(counts.iter().map(|counts| counts[at_sum]).collect())
+}
+
+#[cfg(test)]
+mod ksum_tests {
+    use super::*;
+    use num_bigint::BigInt;
+
+    const EXAMPLE_TERMS: [i32; 6] = [1721, 979, 366, 299, 675, 1456];
+
+    fn values(terms: &[i32], indices: &[usize]) -> Vec<i32> {
+        indices.iter().map(|i| terms[*i]).collect()
+    }
+
+    #[test]
+    fn test_example() {
+        for strategy in Strategy::ALL.iter() {
+            let two = find_terms(&EXAMPLE_TERMS, 2, &2020, *strategy).unwrap();
+            assert_eq!(two, vec![0, 3], "{:?}", strategy);
+            let three = find_terms(&EXAMPLE_TERMS, 3, &2020, *strategy).unwrap();
+            assert_eq!(three, vec![1, 2, 4], "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_each_entry_once() {
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(find_terms(&[1010, 5], 2, &2020, *strategy), None);
+            assert_eq!(
+                find_terms(&[1010, 5, 1010], 2, &2020, *strategy),
+                Some(vec![0, 2])
+            );
+            assert_eq!(find_terms(&[2, 2], 3, &6, *strategy), None);
+        }
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(find_terms(&[], 0, &0, *strategy), Some(vec![]));
+            assert_eq!(find_terms(&[3], 0, &3, *strategy), None);
+            assert_eq!(find_terms(&[1, 3], 1, &3, *strategy), Some(vec![1]));
+            assert_eq!(find_terms(&[1, 3], 3, &4, *strategy), None);
+            assert_eq!(find_terms(&[i32::MAX, i32::MAX, 2], 2, &0, *strategy), None);
+        }
+    }
+
+    #[test]
+    fn test_strategies_agree() {
+        let terms: Vec<i32> = (0..24).map(|i| (i * 37 + 11) % 50 - 20).collect();
+        for k in 0..6 {
+            for sum in -40..60 {
+                let found: Vec<Option<Vec<usize>>> = Strategy::ALL
+                    .iter()
+                    .map(|strategy| find_terms(&terms, k, &sum, *strategy))
+                    .collect();
+                for indices in found.iter() {
+                    assert_eq!(indices.is_some(), found[0].is_some(), "{} {}", k, sum);
+                    if let Some(indices) = indices {
+                        assert_eq!(indices.len(), k);
+                        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+                        assert_eq!(values(&terms, indices).iter().sum::<i32>(), sum);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_half_ending_earliest() {
+        // Both {0, 4} and {1, 2} add up to 3, only the latter fits in front
+        // of {3, 4}.
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(
+                find_terms(&[0, 1, 2, 100, 3], 4, &106, *strategy),
+                Some(vec![1, 2, 3, 4]),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+
+    #[test]
+    fn test_combinations() {
+        let all: Vec<Vec<i32>> = combinations(&EXAMPLE_TERMS, 2, &2020).collect();
+        assert_eq!(all, vec![vec![299, 1721]]);
+        let all: Vec<Vec<i32>> = combinations(&[2, 1, 3, 2, 1, 3], 2, &4).collect();
+        assert_eq!(all, vec![vec![1, 3], vec![2, 2]]);
+        let all: Vec<Vec<i32>> = combinations(&[-1, 0, 1, 2, -1, -4], 3, &0).collect();
+        assert_eq!(all, vec![vec![-1, -1, 2], vec![-1, 0, 1]]);
+        assert_eq!(combinations(&[], 0, &0).count(), 1);
+        assert_eq!(combinations(&[1], 0, &1).count(), 0);
+        assert_eq!(combinations(&[1, 1], 3, &3).count(), 0);
+        assert_eq!(combinations(&[5; 100], 4, &20).count(), 1);
+    }
+
+    #[test]
+    fn test_count_subsets() {
+        assert_eq!(count_subsets(&EXAMPLE_TERMS, &2020), Some(2));
+        assert_eq!(count_subsets(&[1, 1, 2], &2), Some(2));
+        assert_eq!(count_subsets(&[0, 1, 0], &1), Some(4));
+        assert_eq!(count_subsets(&[-3, 3, 1], &0), Some(2));
+        assert_eq!(count_subsets(&[1, 2], &4), Some(0));
+        assert_eq!(count_subsets(&[0; 127], &0), Some(1 << 127));
+        assert_eq!(count_subsets(&[0; 128], &0), None);
+        assert_eq!(count_subsets(&[i32::MAX; 10], &0), Some(1));
+        assert_eq!(count_subsets(&[i32::MAX, i32::MIN, 5], &-1), None);
+
+        assert_eq!(count_subsets_of_size(&EXAMPLE_TERMS, 3, &2020), Some(1));
+        assert_eq!(count_subsets_of_size(&[1, 1, 2, 0], 2, &2), Some(2));
+        assert_eq!(count_subsets_of_size(&[1, 1], 3, &2), Some(0));
+        assert_eq!(count_subsets_of_size(&[], 0, &0), Some(1));
+
+        // A narrow range of sums, but too many sizes to keep a row for each.
+        let ones = vec![1; 10000];
+        assert_eq!(count_subsets_of_size(&ones, 10000, &5000), None);
+        assert_eq!(count_subsets_of_size(&ones, 2, &2), Some(10000 * 9999 / 2));
+    }
+
+    #[test]
+    fn test_counts_match_enumeration() {
+        let terms: Vec<i32> = (0..14).map(|i| (i * 37 + 11) % 23 - 8).collect();
+        for sum in -30..30 {
+            let mut by_size: Vec<u128> = vec![0; terms.len() + 1];
+            for subset in 0u32..1 << terms.len() {
+                let total: i32 = (0..terms.len())
+                    .filter(|i| subset & (1 << i) != 0)
+                    .map(|i| terms[i])
+                    .sum();
+                if total == sum {
+                    by_size[subset.count_ones() as usize] += 1;
+                }
+            }
+            assert_eq!(count_subsets(&terms, &sum), Some(by_size.iter().sum()));
+            for (k, expected) in by_size.iter().enumerate() {
+                assert_eq!(count_subsets_of_size(&terms, k, &sum), Some(*expected));
+                let distinct = combinations(&terms, k, &sum).count();
+                assert_eq!(distinct > 0, *expected > 0, "{} {}", k, sum);
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_large_input() {
+        // Distinct values, as in an expense report.
+        let terms: Vec<i32> = (0..5000).map(|i| (i * 7919) % 20_000 + 1).collect();
+        let count = count_subsets(&terms, &2020).unwrap();
+        let pairs = count_subsets_of_size(&terms, 2, &2020).unwrap();
+        assert_eq!(pairs, combinations(&terms, 2, &2020).count() as u128);
+        assert!(count > pairs);
+    }
+
+    #[test]
+    fn test_wide_terms() {
+        let terms: Vec<u128> = vec![u128::MAX - 5, 3, 5, u128::MAX - 2];
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(
+                find_terms(&terms, 2, &u128::MAX, *strategy),
+                Some(vec![0, 2])
+            );
+        }
+        let all: Vec<Vec<u128>> = combinations(&terms, 2, &u128::MAX).collect();
+        assert_eq!(all, vec![vec![5, u128::MAX - 5]]);
+        assert_eq!(count_subsets(&terms, &u128::MAX), None);
+        assert_eq!(count_subsets(&terms, &8), Some(1));
+
+        let huge = BigInt::from(10).pow(40);
+        let terms: Vec<BigInt> = vec![
+            huge.clone(),
+            BigInt::from(-7),
+            -huge.clone() + 3,
+            BigInt::from(4),
+        ];
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(
+                find_terms(&terms, 3, &BigInt::from(-4), *strategy),
+                Some(vec![0, 1, 2])
+            );
+        }
+        // Both signs that far apart span too wide a table.
+        assert_eq!(count_subsets(&terms, &BigInt::from(-3)), None);
+        let terms: Vec<BigInt> = vec![huge, BigInt::from(-7), BigInt::from(4), BigInt::from(3)];
+        assert_eq!(count_subsets(&terms, &BigInt::from(-3)), Some(1));
+        assert_eq!(count_subsets_of_size(&terms, 2, &BigInt::from(7)), Some(1));
+    }
+}
@@ -0,0 +1,215 @@
+pub mod ksum;
+pub mod term;
+
+use ksum::{combinations, find_terms, Strategy};
+use num_bigint::BigInt;
+use std::error;
+use std::fmt;
+use term::{product, Term};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // Counted from 1.
+    pub line: usize,
+    pub text: String,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {:?} is not a term: {}",
+            self.line, self.text, self.message
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl ParseError {
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"line":{},"text":{},"message":{}}}"#,
+            self.line,
+            json_string(&self.text),
+            json_string(&self.message)
+        )
+    }
+}
+
+// One term per line, blank lines are skipped.
+pub fn parse_terms<T>(input: &str) -> Result<Vec<T>, ParseError>
+where
+    T: Term,
+    T::Err: fmt::Display,
+{
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            line.trim().parse::<T>().map_err(|error| ParseError {
+                line: index + 1,
+                text: String::from(line),
+                message: error.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution<T: Term> {
+    pub terms: Vec<T>,
+    pub indices: Vec<usize>,
+    pub product: BigInt,
+}
+
+impl<T: Term> Solution<T> {
+    fn new(terms: &[T], indices: Vec<usize>) -> Self {
+        let terms: Vec<T> = indices.iter().map(|i| terms[*i].clone()).collect();
+        let product = product(&terms);
+        Solution {
+            terms,
+            indices,
+            product,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"terms":{},"indices":{},"product":{}}}"#,
+            json_array(&self.terms),
+            json_array(&self.indices),
+            self.product
+        )
+    }
+}
+
+pub fn solve<T: Term>(terms: &[T], k: usize, sum: &T, strategy: Strategy) -> Option<Solution<T>> {
+    let indices = find_terms(terms, k, sum, strategy)?;
+    Some(Solution::new(terms, indices))
+}
+
+// Every distinct combination, each with the first entries that have its
+// values.
+pub fn solve_all<T: Term>(terms: &[T], k: usize, sum: &T) -> Vec<Solution<T>> {
+    combinations(terms, k, sum)
+        .map(|combination| {
+            let mut used = vec![false; terms.len()];
+            let mut indices: Vec<usize> = combination
+                .iter()
+                .map(|value| {
+                    let index = (0..terms.len())
+                        .find(|i| !used[*i] && terms[*i] == *value)
+                        .unwrap();
+                    used[index] = true;
+                    index
+                })
+                .collect();
+            indices.sort_unstable();
+            Solution::new(terms, indices)
+        })
+        .collect()
+}
+
+pub fn json_array<T: fmt::Display>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod day1_tests {
+    use super::*;
+
+    const EXAMPLE_TERMS: [i32; 6] = [1721, 979, 366, 299, 675, 1456];
+
+    #[test]
+    fn test_parse_terms() {
+        assert_eq!(
+            parse_terms::<i64>("1721\n979\n\n 366 \n").unwrap(),
+            vec![1721, 979, 366]
+        );
+        let error = parse_terms::<i64>("1721\n\n97x\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.text, "97x");
+        assert_eq!(
+            error.to_string(),
+            "line 3: \"97x\" is not a term: invalid digit found in string"
+        );
+        assert_eq!(
+            error.to_json(),
+            r#"{"line":3,"text":"97x","message":"invalid digit found in string"}"#
+        );
+    }
+
+    #[test]
+    fn test_example_two_terms() {
+        let solution = solve(&EXAMPLE_TERMS, 2, &2020, Strategy::HashSet).unwrap();
+        assert_eq!(solution.terms, vec![1721, 299]);
+    }
+
+    #[test]
+    fn test_example_three_terms() {
+        let solution = solve(&EXAMPLE_TERMS, 3, &2020, Strategy::HashSet).unwrap();
+        assert_eq!(solution.terms, vec![979, 366, 675]);
+    }
+
+    #[test]
+    fn test_solve() {
+        let solution = solve(&EXAMPLE_TERMS, 3, &2020, Strategy::TwoPointer).unwrap();
+        assert_eq!(solution.terms, vec![979, 366, 675]);
+        assert_eq!(solution.indices, vec![1, 2, 4]);
+        assert_eq!(solution.product, BigInt::from(241861950));
+        assert_eq!(
+            solution.to_json(),
+            r#"{"terms":[979,366,675],"indices":[1,2,4],"product":241861950}"#
+        );
+        assert_eq!(solve(&EXAMPLE_TERMS, 2, &2021, Strategy::HashSet), None);
+
+        let terms = [i64::MAX, i64::MAX, i64::MIN];
+        let overflowing = solve(&terms, 3, &(i64::MAX - 1), Strategy::HashSet).unwrap();
+        let expected = BigInt::from(i64::MAX) * BigInt::from(i64::MAX) * BigInt::from(i64::MIN);
+        assert_eq!(overflowing.product, expected);
+
+        let terms: Vec<u128> = parse_terms(&format!("{}\n7\n1\n", u128::MAX - 1)).unwrap();
+        let wide = solve(&terms, 2, &u128::MAX, Strategy::MeetInTheMiddle).unwrap();
+        assert_eq!(wide.indices, vec![0, 2]);
+        assert_eq!(wide.product, BigInt::from(u128::MAX - 1));
+    }
+
+    #[test]
+    fn test_solve_all() {
+        let solutions = solve_all(&[3, 1, 2, 1, 3, 2], 2, &4);
+        let found: Vec<(Vec<i32>, Vec<usize>)> = solutions
+            .into_iter()
+            .map(|solution| (solution.terms, solution.indices))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(vec![3, 1], vec![0, 1]), (vec![2, 2], vec![2, 5])]
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
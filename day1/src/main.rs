@@ -1,79 +1,199 @@
+use day1::ksum::{count_subsets, count_subsets_of_size, Strategy};
+use day1::term::Term;
+use day1::{json_string, parse_terms, solve, solve_all, Solution};
+use num_bigint::BigInt;
 use std::env;
+use std::fmt;
 use std::fs;
+use std::process;
 
-fn find_terms2(terms: &[i32], sum: i32) -> Option<(i32, i32)> {
-    terms.iter().enumerate().find_map(|(i1, t1)| {
-        let needle: i32 = sum - t1;
-        if let Some(i2) = terms.iter().skip(i1).position(|t2| *t2 == needle) {
-            Some((*t1, terms[i1 + i2]))
-        } else {
-            None
-        }
-    })
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
 }
 
-fn find_terms3(terms: &[i32], sum: i32) -> Option<(i32, i32, i32)> {
-    terms.iter().enumerate().find_map(|(i1, t1)| {
-        if let Some((t2, t3)) = find_terms2(&terms[i1 + 1..], sum - t1) {
-            Some((*t1, t2, t3))
-        } else {
-            None
-        }
-    })
+struct Options {
+    strategy: Strategy,
+    all: bool,
+    counting: bool,
+    format: Format,
+    sum: String,
 }
 
-fn solve_part1(terms: &[i32], sum: i32) {
-    match find_terms2(&terms[..], sum) {
-        Some((t1, t2)) => {
-            println!("{} + {} = {}", t1, t2, t1 + t2);
-            println!("{} * {} = {}", t1, t2, t1 * t2);
-        }
-        None => {
-            println!("Failed to find terms that add up to {}", sum);
-        }
-    }
+struct Part<T: Term> {
+    k: usize,
+    solutions: Vec<Solution<T>>,
+    subsets: Option<Option<u128>>,
 }
 
-fn solve_part2(terms: &[i32], sum: i32) {
-    match find_terms3(&terms[..], sum) {
-        Some((t1, t2, t3)) => {
-            println!("{} + {} + {} = {}", t1, t2, t3, t1 + t2 + t3);
-            println!("{} * {} * {} = {}", t1, t2, t3, t1 * t2 * t3);
+fn print_text<T: Term>(parts: &[Part<T>], subsets: Option<Option<u128>>, sum: &T, all: bool) {
+    for part in parts.iter() {
+        for solution in part.solutions.iter() {
+            let terms: Vec<String> = solution.terms.iter().map(|t| t.to_string()).collect();
+            println!("{} = {}", terms.join(" + "), sum);
+            println!("{} = {}", terms.join(" * "), solution.product);
         }
-        None => {
+        if all {
+            println!(
+                "{} distinct combinations of {} terms add up to {}",
+                part.solutions.len(),
+                part.k,
+                sum
+            );
+        } else if part.solutions.is_empty() {
             println!("Failed to find terms that add up to {}", sum);
         }
+        if let Some(count) = part.subsets {
+            print_count(count, &format!("{} entries", part.k), sum);
+        }
+    }
+    if let Some(count) = subsets {
+        print_count(count, "entries", sum);
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let filename = args.get(1).expect("Usage: day1 input-filename");
+fn print_count<T: Term>(count: Option<u128>, size: &str, sum: &T) {
+    match count {
+        Some(count) => println!("{} subsets of {} add up to {}", count, size, sum),
+        None => println!("Failed to count subsets of {} that add up to {}", size, sum),
+    }
+}
 
-    println!("Reading terms from {}", filename);
-    let input = fs::read_to_string(filename).expect("Failed to read file");
+fn json_count(count: Option<u128>) -> String {
+    match count {
+        Some(count) => count.to_string(),
+        None => String::from("null"),
+    }
+}
 
-    let terms: Vec<i32> = input
-        .lines()
-        .map(|s| s.parse::<i32>().expect("Failed to parse i32"))
+fn print_json<T: Term>(parts: &[Part<T>], subsets: Option<Option<u128>>, sum: &T) {
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|part| {
+            let solutions: Vec<String> = part.solutions.iter().map(|s| s.to_json()).collect();
+            let subsets = match part.subsets {
+                Some(count) => format!(r#","subsets":{}"#, json_count(count)),
+                None => String::new(),
+            };
+            format!(
+                r#"{{"k":{},"solutions":[{}]{}}}"#,
+                part.k,
+                solutions.join(","),
+                subsets
+            )
+        })
         .collect();
-    solve_part1(&terms[..], 2020);
-    solve_part2(&terms[..], 2020);
+    let subsets = match subsets {
+        Some(count) => format!(r#","subsets":{}"#, json_count(count)),
+        None => String::new(),
+    };
+    println!(
+        r#"{{"sum":{},"parts":[{}]{}}}"#,
+        sum,
+        parts.join(","),
+        subsets
+    );
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn run<T>(filename: &str, input: &str, options: &Options)
+where
+    T: Term,
+    T::Err: fmt::Display,
+{
+    let terms: Vec<T> = match parse_terms(input) {
+        Ok(terms) => terms,
+        Err(error) => {
+            match options.format {
+                Format::Text => eprintln!("{}: {}", filename, error),
+                Format::Json => println!(r#"{{"error":{}}}"#, error.to_json()),
+            }
+            process::exit(1);
+        }
+    };
+    let sum: T = match options.sum.parse() {
+        Ok(sum) => sum,
+        Err(error) => {
+            match options.format {
+                Format::Text => eprintln!("Invalid sum {:?}: {}", options.sum, error),
+                Format::Json => println!(
+                    r#"{{"error":{{"text":{},"message":{}}}}}"#,
+                    json_string(&options.sum),
+                    json_string(&error.to_string())
+                ),
+            }
+            process::exit(1);
+        }
+    };
 
-    const EXAMPLE_TERMS: [i32; 6] = [1721, 979, 366, 299, 675, 1456];
+    let parts: Vec<Part<T>> = [2, 3]
+        .iter()
+        .map(|k| Part {
+            k: *k,
+            solutions: if options.all {
+                solve_all(&terms, *k, &sum)
+            } else {
+                solve(&terms, *k, &sum, options.strategy)
+                    .into_iter()
+                    .collect()
+            },
+            subsets: if options.counting {
+                Some(count_subsets_of_size(&terms, *k, &sum))
+            } else {
+                None
+            },
+        })
+        .collect();
+    let subsets = if options.counting {
+        Some(count_subsets(&terms, &sum))
+    } else {
+        None
+    };
+    match options.format {
+        Format::Text => print_text(&parts, subsets, &sum, options.all),
+        Format::Json => print_json(&parts, subsets, &sum),
+    }
+}
 
-    #[test]
-    fn test_example_two_terms() {
-        assert_eq!(find_terms2(&EXAMPLE_TERMS, 2020), Some((1721, 299)));
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = "Usage: day1 input-filename [hash-set|two-pointer|meet-in-the-middle] [--all] [--count] [--format text|json] [--sum n] [--type i32|i64|u128|big]";
+    let filename = args.get(1).expect(usage);
+    let mut options = Options {
+        strategy: Strategy::HashSet,
+        all: false,
+        counting: false,
+        format: Format::Text,
+        sum: String::from("2020"),
+    };
+    let mut term_type = "i64";
+    let mut args = args.iter().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--all" => options.all = true,
+            "--count" => options.counting = true,
+            "--format" => {
+                options.format = match args.next().map(|name| name.as_str()) {
+                    Some("text") => Format::Text,
+                    Some("json") => Format::Json,
+                    _ => panic!("{}", usage),
+                }
+            }
+            "--sum" => options.sum = args.next().expect(usage).clone(),
+            "--type" => term_type = args.next().expect(usage),
+            name => options.strategy = Strategy::parse(name).expect(usage),
+        }
     }
 
-    #[test]
-    fn test_example_three_terms() {
-        assert_eq!(find_terms3(&EXAMPLE_TERMS, 2020), Some((979, 366, 675)));
+    if options.format == Format::Text {
+        println!("Reading terms from {}", filename);
+    }
+    let input = fs::read_to_string(filename).expect("Failed to read file");
+    match term_type {
+        "i32" => run::<i32>(filename, &input, &options),
+        "i64" => run::<i64>(filename, &input, &options),
+        "u128" => run::<u128>(filename, &input, &options),
+        "big" => run::<BigInt>(filename, &input, &options),
+        _ => panic!("{}", usage),
     }
 }
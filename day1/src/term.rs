@@ -0,0 +1,133 @@
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use std::fmt;
+use std::hash::Hash;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+// The types an expense report can be read as. Sums and products never
+// overflow: terms that fit in an i64 are added up as i128, which holds the
+// sum of any number of them, and everything else as BigInt.
+pub trait Term: Clone + fmt::Debug + fmt::Display + PartialEq + FromStr {
+    fn to_i64(&self) -> Option<i64>;
+    fn to_bigint(&self) -> BigInt;
+}
+
+impl Term for i32 {
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self as i64)
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        BigInt::from(*self)
+    }
+}
+
+impl Term for i64 {
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        BigInt::from(*self)
+    }
+}
+
+impl Term for u128 {
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        BigInt::from(*self)
+    }
+}
+
+impl Term for BigInt {
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        self.clone()
+    }
+}
+
+// What the solvers add up terms as.
+pub(crate) trait Number:
+    Clone + Ord + Hash + Zero + ToPrimitive + Add<Output = Self> + Sub<Output = Self> + 'static
+{
+}
+
+impl<N> Number for N where
+    N: Clone + Ord + Hash + Zero + ToPrimitive + Add<Output = N> + Sub<Output = N> + 'static
+{
+}
+
+pub(crate) enum Widened {
+    Small(Vec<i128>, i128),
+    Big(Vec<BigInt>, BigInt),
+}
+
+pub(crate) fn widen<T: Term>(terms: &[T], sum: &T) -> Widened {
+    let small: Option<Vec<i128>> = terms
+        .iter()
+        .map(|term| term.to_i64().map(|term| term as i128))
+        .collect();
+    match (small, sum.to_i64()) {
+        (Some(terms), Some(sum)) => Widened::Small(terms, sum as i128),
+        _ => Widened::Big(
+            terms.iter().map(|term| term.to_bigint()).collect(),
+            sum.to_bigint(),
+        ),
+    }
+}
+
+// Multiplies as i128 until that overflows, and goes on as BigInt from there.
+pub fn product<T: Term>(terms: &[T]) -> BigInt {
+    let mut small: i128 = 1;
+    for (index, term) in terms.iter().enumerate() {
+        let next = term
+            .to_i64()
+            .and_then(|term| small.checked_mul(term as i128));
+        match next {
+            Some(next) => small = next,
+            None => {
+                return terms[index..]
+                    .iter()
+                    .fold(BigInt::from(small), |product, term| {
+                        product * term.to_bigint()
+                    })
+            }
+        }
+    }
+    BigInt::from(small)
+}
+
+#[cfg(test)]
+mod term_tests {
+    use super::*;
+
+    #[test]
+    fn test_product() {
+        assert_eq!(product(&[1721, 299]), BigInt::from(514579));
+        assert_eq!(product::<i64>(&[]), BigInt::from(1));
+        assert_eq!(
+            product(&[i64::MAX, i64::MAX, -2]),
+            BigInt::from(i64::MAX) * BigInt::from(i64::MAX) * BigInt::from(-2)
+        );
+        assert_eq!(product(&[u128::MAX, 2, 0]), BigInt::zero());
+        let big = BigInt::from(u128::MAX) * BigInt::from(3);
+        assert_eq!(
+            product(&[big.clone(), BigInt::from(2)]),
+            big * BigInt::from(2)
+        );
+    }
+
+    #[test]
+    fn test_widen() {
+        assert!(matches!(widen(&[1i64, -2], &3), Widened::Small(_, 3)));
+        assert!(matches!(widen(&[1u128, u128::MAX], &3), Widened::Big(_, _)));
+        assert!(matches!(widen(&[1u128], &u128::MAX), Widened::Big(_, _)));
+    }
+}
@@ -0,0 +1,253 @@
+use super::report::{json_string, LineReport};
+use std::fmt::Write;
+
+// How two policies agree over a database. Lines that do not parse are
+// invalid under every policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PairCounts {
+    pub both: usize,
+    pub first_only: usize,
+    pub second_only: usize,
+    pub neither: usize,
+}
+
+// A confusion matrix for every pair of policies, and the lines where any of
+// the policies disagree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison<'a> {
+    pub pairs: Vec<(usize, usize, PairCounts)>,
+    pub differing: Vec<&'a LineReport>,
+}
+
+fn valid(report: &LineReport, policy: usize) -> bool {
+    report.record.is_ok() && report.results[policy].is_ok()
+}
+
+pub fn compare(reports: &[LineReport], policy_count: usize) -> Comparison<'_> {
+    let mut pairs = Vec::new();
+    for first in 0..policy_count {
+        for second in first + 1..policy_count {
+            let mut counts = PairCounts::default();
+            for report in reports.iter() {
+                match (valid(report, first), valid(report, second)) {
+                    (true, true) => counts.both += 1,
+                    (true, false) => counts.first_only += 1,
+                    (false, true) => counts.second_only += 1,
+                    (false, false) => counts.neither += 1,
+                }
+            }
+            pairs.push((first, second, counts));
+        }
+    }
+    let differing = reports
+        .iter()
+        .filter(|report| (1..policy_count).any(|policy| valid(report, policy) != valid(report, 0)))
+        .collect();
+    Comparison { pairs, differing }
+}
+
+fn verdict(valid: bool) -> &'static str {
+    if valid {
+        "valid"
+    } else {
+        "invalid"
+    }
+}
+
+// A matrix per pair of policies, rows by the first and columns by the
+// second, followed by the differing lines with the verdict of each policy.
+pub fn comparison_table(comparison: &Comparison, names: &[&str]) -> String {
+    let mut table = String::new();
+    for (first, second, counts) in comparison.pairs.iter() {
+        let corner = format!("{} \\ {}", names[*first], names[*second]);
+        let width = corner.len().max(7);
+        let valid_width = counts.both.max(counts.second_only).to_string().len().max(5);
+        let invalid_width = counts
+            .first_only
+            .max(counts.neither)
+            .to_string()
+            .len()
+            .max(7);
+        writeln!(
+            table,
+            "{:width$}  {:>valid$}  {:>invalid$}",
+            corner,
+            "valid",
+            "invalid",
+            width = width,
+            valid = valid_width,
+            invalid = invalid_width
+        )
+        .unwrap();
+        for (label, valid_count, invalid_count) in [
+            ("valid", counts.both, counts.first_only),
+            ("invalid", counts.second_only, counts.neither),
+        ] {
+            writeln!(
+                table,
+                "{:width$}  {:>valid$}  {:>invalid$}",
+                label,
+                valid_count,
+                invalid_count,
+                width = width,
+                valid = valid_width,
+                invalid = invalid_width
+            )
+            .unwrap();
+        }
+        table.push('\n');
+    }
+
+    let line_width = comparison
+        .differing
+        .iter()
+        .map(|report| report.line.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    write!(table, "{:>width$}", "line", width = line_width).unwrap();
+    for name in names.iter() {
+        write!(table, "  {:width$}", name, width = name.len().max(7)).unwrap();
+    }
+    table.push_str("  text\n");
+    for report in comparison.differing.iter() {
+        write!(table, "{:>width$}", report.line, width = line_width).unwrap();
+        for (policy, name) in names.iter().enumerate() {
+            let width = name.len().max(7);
+            write!(
+                table,
+                "  {:width$}",
+                verdict(valid(report, policy)),
+                width = width
+            )
+            .unwrap();
+        }
+        writeln!(table, "  {}", report.text).unwrap();
+    }
+    table
+}
+
+pub fn comparison_json(comparison: &Comparison, names: &[&str]) -> String {
+    let pairs: Vec<String> = comparison
+        .pairs
+        .iter()
+        .map(|(first, second, counts)| {
+            format!(
+                r#"{{"first":{},"second":{},"both":{},"first_only":{},"second_only":{},"neither":{}}}"#,
+                json_string(names[*first]),
+                json_string(names[*second]),
+                counts.both,
+                counts.first_only,
+                counts.second_only,
+                counts.neither
+            )
+        })
+        .collect();
+    let differing: Vec<String> = comparison
+        .differing
+        .iter()
+        .map(|report| {
+            let verdicts: Vec<String> = names
+                .iter()
+                .enumerate()
+                .map(|(policy, name)| format!("{}:{}", json_string(name), valid(report, policy)))
+                .collect();
+            format!(
+                r#"{{"line":{},"text":{},"valid":{{{}}}}}"#,
+                report.line,
+                json_string(&report.text),
+                verdicts.join(",")
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"pairs":[{}],"differing":[{}]}}"#,
+        pairs.join(","),
+        differing.join(",")
+    )
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use super::*;
+    use crate::policy::{IndexMode, Not, OccurrenceRange, PasswordPolicy, PositionXor};
+    use crate::report::report;
+
+    const EXAMPLE: &str = "1-3 a: abcde
+1-3 b: cdefg
+
+2-9 c: ccccccccc
+3 c: \"c\"
+";
+
+    fn reports(policies: &[&dyn PasswordPolicy]) -> Vec<LineReport> {
+        report(EXAMPLE, policies)
+    }
+
+    #[test]
+    fn test_compare() {
+        let not_range = Not(Box::new(OccurrenceRange));
+        let reports = reports(&[&OccurrenceRange, &PositionXor(IndexMode::Char), &not_range]);
+        let comparison = compare(&reports, 3);
+        let pairs: Vec<(usize, usize)> = comparison
+            .pairs
+            .iter()
+            .map(|(first, second, _)| (*first, *second))
+            .collect();
+        assert_eq!(pairs, vec![(0, 1), (0, 2), (1, 2)]);
+        assert_eq!(
+            comparison.pairs[0].2,
+            PairCounts {
+                both: 1,
+                first_only: 1,
+                second_only: 0,
+                neither: 2,
+            }
+        );
+        assert_eq!(
+            comparison.pairs[1].2,
+            PairCounts {
+                both: 0,
+                first_only: 2,
+                second_only: 1,
+                neither: 1,
+            }
+        );
+        let lines: Vec<usize> = comparison
+            .differing
+            .iter()
+            .map(|report| report.line)
+            .collect();
+        assert_eq!(lines, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_comparison_table() {
+        let reports = reports(&[&OccurrenceRange, &PositionXor(IndexMode::Char)]);
+        let comparison = compare(&reports, 2);
+        assert_eq!(
+            comparison_table(&comparison, &["sled", "toboggan"]),
+            "sled \\ toboggan  valid  invalid
+valid                1        1
+invalid              0        2
+
+line  sled     toboggan  text
+   4  valid    invalid   2-9 c: ccccccccc
+"
+        );
+    }
+
+    #[test]
+    fn test_comparison_json() {
+        let reports = reports(&[&OccurrenceRange, &PositionXor(IndexMode::Char)]);
+        let comparison = compare(&reports, 2);
+        assert_eq!(
+            comparison_json(&comparison, &["sled", "toboggan"]),
+            concat!(
+                r#"{"pairs":[{"first":"sled","second":"toboggan","both":1,"first_only":1,"#,
+                r#""second_only":0,"neither":2}],"differing":[{"line":4,"#,
+                r#""text":"2-9 c: ccccccccc","valid":{"sled":true,"toboggan":false}}]}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use super::policy::{
+    All, Any, CaseInsensitive, CharClass, Forbidden, IndexMode, MinClassCount, Not,
+    OccurrenceRange, PasswordPolicy, PositionXor,
+};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+// A small language for combining policies:
+//
+//   range                 the needle occurs first to second times
+//   position              the needle is at exactly one of the positions
+//   position(grapheme)    the same counting bytes, chars or graphemes, chars
+//                         by default
+//   min(digit, 2)         at least 2 characters of a class, one of lower,
+//                         upper, digit and symbol
+//   forbid("abc", "123")  none of the substrings occur
+//   nocase(expr)          expr with needle and password in lower case
+//   not, and, or          in order of precedence, with parentheses
+//
+// A config file defines one policy per line as name = expr, and later
+// definitions can refer to earlier ones by name. Everything after a # is a
+// comment.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DslError {
+    // Counted from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for DslError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(usize),
+    Text(String),
+    Open,
+    Close,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => text.push(escaped),
+                            None => return Err(String::from("unterminated string")),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(String::from("unterminated string")),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            c if c.is_whitespace() => (),
+            c if c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(*digit);
+                    chars.next();
+                }
+                let number = digits
+                    .parse()
+                    .map_err(|_| format!("{} is too large", digits))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    word.push(*c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("unexpected {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Range,
+    Position(IndexMode),
+    MinClass(CharClass, usize),
+    Forbid(Vec<String>),
+    NoCase(Box<Expr>),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    fn build(&self) -> Box<dyn PasswordPolicy> {
+        match self {
+            Expr::Range => Box::new(OccurrenceRange),
+            Expr::Position(mode) => Box::new(PositionXor(*mode)),
+            Expr::MinClass(class, min) => Box::new(MinClassCount {
+                class: *class,
+                min: *min,
+            }),
+            Expr::Forbid(substrings) => Box::new(Forbidden(substrings.clone())),
+            Expr::NoCase(expr) => Box::new(CaseInsensitive(expr.build())),
+            Expr::Not(expr) => Box::new(Not(expr.build())),
+            Expr::And(exprs) => Box::new(All(exprs.iter().map(|expr| expr.build()).collect())),
+            Expr::Or(exprs) => Box::new(Any(exprs.iter().map(|expr| expr.build()).collect())),
+        }
+    }
+}
+
+const KEYWORDS: [&str; 8] = [
+    "range", "position", "min", "forbid", "nocase", "not", "and", "or",
+];
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    next: usize,
+    definitions: &'a HashMap<String, Expr>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.advance() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {} but found {}", expected, token)),
+            None => Err(format!("expected {} at the end", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut exprs = vec![self.and()?];
+        while self.at_word("or") {
+            self.advance();
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut exprs = vec![self.unary()?];
+        while self.at_word("and") {
+            self.advance();
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.at_word("not") {
+            self.advance();
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.advance() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::Word(word)) => self.word(&word),
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err(String::from("unexpected end")),
+        }
+    }
+
+    fn word(&mut self, word: &str) -> Result<Expr, String> {
+        match word {
+            "range" => Ok(Expr::Range),
+            "position" => {
+                if self.peek() != Some(&Token::Open) {
+                    return Ok(Expr::Position(IndexMode::Char));
+                }
+                self.advance();
+                let mode = match self.advance() {
+                    Some(Token::Word(name)) => IndexMode::parse(&name)
+                        .ok_or_else(|| format!("unknown index mode {}", name))?,
+                    _ => return Err(String::from("expected an index mode")),
+                };
+                self.expect(Token::Close)?;
+                Ok(Expr::Position(mode))
+            }
+            "min" => {
+                self.expect(Token::Open)?;
+                let class = match self.advance() {
+                    Some(Token::Word(name)) => CharClass::ALL
+                        .iter()
+                        .copied()
+                        .find(|class| class.name() == name)
+                        .ok_or_else(|| format!("unknown character class {}", name))?,
+                    _ => return Err(String::from("expected a character class")),
+                };
+                self.expect(Token::Comma)?;
+                let min = match self.advance() {
+                    Some(Token::Number(min)) => min,
+                    _ => return Err(String::from("expected a number")),
+                };
+                self.expect(Token::Close)?;
+                Ok(Expr::MinClass(class, min))
+            }
+            "forbid" => {
+                self.expect(Token::Open)?;
+                let mut substrings: Vec<String> = Vec::new();
+                loop {
+                    match self.advance() {
+                        Some(Token::Text(text)) => substrings.push(text),
+                        _ => return Err(String::from("expected a string")),
+                    }
+                    match self.advance() {
+                        Some(Token::Comma) => (),
+                        Some(Token::Close) => break,
+                        _ => return Err(String::from("expected , or )")),
+                    }
+                }
+                Ok(Expr::Forbid(substrings))
+            }
+            "nocase" => {
+                self.expect(Token::Open)?;
+                let expr = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(Expr::NoCase(Box::new(expr)))
+            }
+            name => match self.definitions.get(name) {
+                Some(expr) => Ok(expr.clone()),
+                None => Err(format!("unknown policy {}", name)),
+            },
+        }
+    }
+}
+
+fn parse_expr(text: &str, definitions: &HashMap<String, Expr>) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        next: 0,
+        definitions,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        Some(token) => Err(format!("unexpected {}", token)),
+        None => Ok(expr),
+    }
+}
+
+pub fn parse_policy(text: &str) -> Result<Box<dyn PasswordPolicy>, DslError> {
+    let expr =
+        parse_expr(text, &HashMap::new()).map_err(|message| DslError { line: 1, message })?;
+    Ok(expr.build())
+}
+
+// Everything from a # that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => (),
+        }
+    }
+    line
+}
+
+pub struct NamedPolicy {
+    pub name: String,
+    pub policy: Box<dyn PasswordPolicy>,
+}
+
+pub fn parse_config(text: &str) -> Result<Vec<NamedPolicy>, DslError> {
+    let mut definitions: HashMap<String, Expr> = HashMap::new();
+    let mut policies: Vec<NamedPolicy> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| DslError {
+            line: index + 1,
+            message,
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, definition) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), &line[equals + 1..]),
+            None => return Err(error(String::from("expected name = policy"))),
+        };
+        let valid_name = !name.is_empty()
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && !name.starts_with(|c: char| c.is_ascii_digit());
+        if !valid_name {
+            return Err(error(format!("{:?} is not a valid name", name)));
+        }
+        if KEYWORDS.contains(&name) || definitions.contains_key(name) {
+            return Err(error(format!("{} is already defined", name)));
+        }
+        let expr = parse_expr(definition, &definitions).map_err(error)?;
+        policies.push(NamedPolicy {
+            name: String::from(name),
+            policy: expr.build(),
+        });
+        definitions.insert(String::from(name), expr);
+    }
+    Ok(policies)
+}
+
+#[cfg(test)]
+mod dsl_tests {
+    use super::*;
+    use crate::Record;
+
+    fn check(policy: &str, line: &str) -> bool {
+        parse_policy(policy)
+            .unwrap()
+            .check(&Record::parse(line).unwrap())
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert!(check("range", "1-3 a: abcde"));
+        assert!(!check("position", "2-9 c: ccccccccc"));
+        assert!(check("range or position", "1-3 b: bxb"));
+        assert!(!check("range and position", "1-3 b: bxb"));
+        assert!(check("not range and position", "1-3 a: axxaaa"));
+        assert!(!check("not (range or position)", "1-3 a: axxaaa"));
+        assert!(check("min(digit, 2) and min(upper,1)", "1-1 a: A1b2"));
+        assert!(!check(r#"forbid("12", "x\"y")"#, r#"1-1 a: ax"y"#));
+        assert!(check("nocase(range)", "2-2 a: Aa"));
+        assert!(check("position(byte)", "3-4 b: åbc"));
+        assert!(!check("position", "3-4 b: åbc"));
+        assert!(check("range or range and not range", "1-1 a: a"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let message = |text: &str| parse_policy(text).err().unwrap().message;
+        assert_eq!(message("range and"), "unexpected end");
+        assert_eq!(message("(range"), "expected ) at the end");
+        assert_eq!(message("range position"), "unexpected position");
+        assert_eq!(message("min(vowel, 1)"), "unknown character class vowel");
+        assert_eq!(message("forbid()"), "expected a string");
+        assert_eq!(message(r#"forbid("a"#), "unterminated string");
+        assert_eq!(message("sled"), "unknown policy sled");
+        assert_eq!(message("position(word)"), "unknown index mode word");
+        assert_eq!(message("range & position"), "unexpected '&'");
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = "# from the puzzle
+sled = range
+toboggan = position   # part two
+
+strict = (sled or toboggan) and forbid(\"123\")
+hashless = forbid(\"#\", \"\\\"#\") # no hashes
+";
+        let policies = parse_config(config).unwrap();
+        let names: Vec<&str> = policies.iter().map(|named| named.name.as_str()).collect();
+        assert_eq!(names, vec!["sled", "toboggan", "strict", "hashless"]);
+        let record = Record::parse("1-3 a: a123").unwrap();
+        let checks: Vec<bool> = policies
+            .iter()
+            .map(|named| named.policy.check(&record))
+            .collect();
+        assert_eq!(checks, vec![true, true, false, true]);
+
+        let error = parse_config("sled = range\nsled = position\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "line 2: sled is already defined");
+        let error = parse_config("\nrange = position\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: range is already defined");
+        let error = parse_config("a = later\nlater = range\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 1: unknown policy later");
+        let error = parse_config("just range\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 1: expected name = policy");
+    }
+}
@@ -0,0 +1,129 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+
+pub mod compare;
+pub mod dsl;
+pub mod policy;
+pub mod report;
+pub mod stream;
+
+use policy::PasswordPolicy;
+use std::error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordError {
+    // Not of the form "1-3 a: abcde".
+    Malformed,
+    // One of the numbers does not fit in a usize.
+    NumberTooLarge(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Malformed => write!(f, "expected a line like \"1-3 a: abcde\""),
+            RecordError::NumberTooLarge(number) => write!(f, "{} is too large", number),
+        }
+    }
+}
+
+impl error::Error for RecordError {}
+
+// A line of the password database, like "1-3 a: abcde". What the two
+// numbers and the needle mean is up to the policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub first: usize,
+    pub second: usize,
+    pub needle: String,
+    pub password: String,
+}
+
+impl Record {
+    pub fn parse(line: &str) -> Result<Record, RecordError> {
+        lazy_static! {
+            static ref PARSE_RE: regex::Regex =
+                regex::Regex::new(r"^(\d+)-(\d+) ([^\s:]+): (.*)$").unwrap();
+        }
+
+        let captures = PARSE_RE.captures(line).ok_or(RecordError::Malformed)?;
+        let number = |index: usize| {
+            captures[index]
+                .parse::<usize>()
+                .map_err(|_| RecordError::NumberTooLarge(String::from(&captures[index])))
+        };
+        Ok(Record {
+            first: number(1)?,
+            second: number(2)?,
+            needle: String::from(&captures[3]),
+            password: String::from(&captures[4]),
+        })
+    }
+}
+
+// Lines that do not parse count as invalid.
+pub fn count_valid_passwords(input: &str, policy: &dyn PasswordPolicy) -> usize {
+    input
+        .lines()
+        .filter_map(|line| Record::parse(line).ok())
+        .filter(|record| policy.check(record))
+        .count()
+}
+
+#[cfg(test)]
+mod day2_tests {
+    use super::*;
+    use policy::{IndexMode, OccurrenceRange, PositionXor};
+
+    #[test]
+    fn test_example() {
+        const EXAMPLE: &str = r"1-3 a: abcde
+1-3 b: cdefg
+2-9 c: ccccccccc";
+        assert_eq!(count_valid_passwords(EXAMPLE, &OccurrenceRange), 2);
+        assert_eq!(
+            count_valid_passwords(EXAMPLE, &PositionXor(IndexMode::Char)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(Record::parse(""), Err(RecordError::Malformed));
+        assert_eq!(count_valid_passwords("\n", &OccurrenceRange), 0);
+    }
+
+    #[test]
+    fn test_valid() {
+        let record = Record::parse("8-9 n: nnnnnnnnn").unwrap();
+        assert!(OccurrenceRange.check(&record));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let record = Record::parse("8-9 n: nnnnn").unwrap();
+        assert!(!OccurrenceRange.check(&record));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Record::parse("2-10 ab: xabyab").unwrap(),
+            Record {
+                first: 2,
+                second: 10,
+                needle: String::from("ab"),
+                password: String::from("xabyab"),
+            }
+        );
+        assert_eq!(Record::parse("1-3 a abcde"), Err(RecordError::Malformed));
+        assert_eq!(
+            Record::parse("1-99999999999999999999999 a: b"),
+            Err(RecordError::NumberTooLarge(String::from(
+                "99999999999999999999999"
+            )))
+        );
+    }
+}
@@ -1,114 +1,134 @@
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
-
-use std::cmp;
+use day2::compare::{compare, comparison_json, comparison_table};
+use day2::dsl::parse_config;
+use day2::policy::{IndexMode, OccurrenceRange, PasswordPolicy, PositionXor};
+use day2::report::{failures_json, failures_table, report};
+use day2::stream::{validate_stream, StreamConfig};
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process;
 
-fn is_password_valid1(line: &str) -> Option<bool> {
-    lazy_static! {
-        static ref PARSE_RE: regex::Regex =
-            regex::Regex::new(r"(\d*)-(\d*) ([a-z]): (.*)").unwrap();
-            //regex::Regex::new(r"\(\d*\)-\(\d*\) \([a-z]\): \(.*\)").unwrap();
-    }
-
-    if line.len() == 0 {
-        None
-    } else {
-        let captures = PARSE_RE.captures(line).unwrap();
-        let min = captures.get(1).unwrap().as_str().parse::<usize>().unwrap();
-        let max = captures.get(2).unwrap().as_str().parse::<usize>().unwrap();
-        let needle = captures.get(3).unwrap().as_str().chars().nth(0).unwrap();
-        let password = captures.get(4).unwrap().as_str();
-        let count = password.chars().filter(|c| c == &needle).count();
-        Some(min <= count && count <= max)
-    }
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReportFormat {
+    Table,
+    Json,
 }
 
-fn is_password_valid2(line: &str) -> Option<bool> {
-    lazy_static! {
-        static ref PARSE_RE: regex::Regex =
-            regex::Regex::new(r"(\d*)-(\d*) ([a-z]): (.*)").unwrap();
-            //regex::Regex::new(r"\(\d*\)-\(\d*\) \([a-z]\): \(.*\)").unwrap();
-    }
-
-    if line.len() == 0 {
-        None
-    } else {
-        let captures = PARSE_RE.captures(line).unwrap();
-        let pos1 = captures.get(1).unwrap().as_str().parse::<usize>().unwrap() - 1;
-        let pos2 = captures.get(2).unwrap().as_str().parse::<usize>().unwrap() - 1;
-        let needle = captures.get(3).unwrap().as_str().chars().nth(0).unwrap();
-        let password = captures.get(4).unwrap().as_str();
-        if cmp::max(pos1, pos2) < password.len() {
-            Some(
-                (password.chars().nth(pos1).unwrap() == needle)
-                    ^ (password.chars().nth(pos2).unwrap() == needle),
-            )
-        } else {
-            Some(false)
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = "Usage: day2 input-filename|- [policy-config] [--report table|json] \
+                 [--compare table|json] [--threads n] [--chunk lines] [--index byte|char|grapheme]";
+    let filename = args.get(1).expect(usage);
+    let mut config_filename: Option<&String> = None;
+    let mut report_format: Option<ReportFormat> = None;
+    let mut compare_format: Option<ReportFormat> = None;
+    let mut stream_config = StreamConfig::default();
+    let mut index_mode = IndexMode::Char;
+    let format = |value: Option<&String>| match value.map(|format| format.as_str()) {
+        Some("table") => ReportFormat::Table,
+        Some("json") => ReportFormat::Json,
+        _ => panic!("{}", usage),
+    };
+    let number = |value: Option<&String>| -> usize {
+        value.and_then(|value| value.parse().ok()).expect(usage)
+    };
+    let mut options = args.iter().skip(2);
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--report" => report_format = Some(format(options.next())),
+            "--compare" => compare_format = Some(format(options.next())),
+            "--threads" => stream_config = stream_config.with_threads(number(options.next())),
+            "--chunk" => stream_config = stream_config.with_chunk_lines(number(options.next())),
+            "--index" => {
+                index_mode = options
+                    .next()
+                    .and_then(|mode| IndexMode::parse(mode))
+                    .expect(usage)
+            }
+            _ => config_filename = Some(arg),
         }
     }
-}
 
-fn count_valid_passwords<P>(input: &str, policy: P) -> usize
-where
-    P: Fn(&str) -> Option<bool>,
-{
-    input
-        .lines()
-        .filter(|line| {
-            if let Some(valid) = policy(line) {
-                valid
-            } else {
-                false
+    let (names, policies): (Vec<String>, Vec<Box<dyn PasswordPolicy>>) = match config_filename {
+        Some(config_filename) => {
+            let config = fs::read_to_string(config_filename).expect("Failed to read policy config");
+            match parse_config(&config) {
+                Ok(policies) => policies
+                    .into_iter()
+                    .map(|named| (named.name, named.policy))
+                    .unzip(),
+                Err(error) => {
+                    eprintln!("{}: {}", config_filename, error);
+                    process::exit(1);
+                }
             }
-        })
-        .count()
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let filename = args.get(1).expect("Usage: day1 input-filename");
-
-    println!("Reading terms from {}", filename);
-    let input = fs::read_to_string(filename).expect("Failed to read file");
-
-    let invalid_count = count_valid_passwords(&input, is_password_valid1);
-    println!("Invalid passwords (Policy 1): {}", invalid_count);
-
-    let invalid_count = count_valid_passwords(&input, is_password_valid2);
-    println!("Invalid passwords (Policy 2): {}", invalid_count);
-}
+        }
+        None => (
+            vec![String::from("Policy 1"), String::from("Policy 2")],
+            vec![Box::new(OccurrenceRange), Box::new(PositionXor(index_mode))],
+        ),
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    if compare_format.is_some() && policies.len() < 2 {
+        eprintln!("Comparing needs at least two policies");
+        process::exit(1);
+    }
 
-    #[test]
-    fn test_example() {
-        const EXAMPLE: &str = r"1-3 a: abcde
-1-3 b: cdefg
-2-9 c: ccccccccc";
-        assert_eq!(count_valid_passwords(EXAMPLE, is_password_valid1), 2);
-        assert_eq!(count_valid_passwords(EXAMPLE, is_password_valid2), 1);
+    // Both go to stdout, which either is a table or holds a single JSON
+    // document.
+    if let (Some(report_format), Some(compare_format)) = (report_format, compare_format) {
+        if report_format != compare_format {
+            eprintln!("--report and --compare need the same format");
+            process::exit(1);
+        }
     }
 
-    #[test]
-    fn test_empty() {
-        assert_eq!(is_password_valid1(""), None);
+    let json =
+        report_format == Some(ReportFormat::Json) || compare_format == Some(ReportFormat::Json);
+    if !json {
+        println!("Reading terms from {}", filename);
     }
+    let reader: Box<dyn BufRead> = if filename == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(
+            fs::File::open(filename).expect("Failed to open file"),
+        ))
+    };
+    let policies: Vec<&dyn PasswordPolicy> =
+        policies.iter().map(|policy| policy.as_ref()).collect();
 
-    #[test]
-    fn test_valid() {
-        let line = "8-9 n: nnnnnnnnn";
-        assert_eq!(is_password_valid1(line), Some(true));
+    if report_format.is_some() || compare_format.is_some() {
+        let mut input = String::new();
+        { reader }
+            .read_to_string(&mut input)
+            .expect("Failed to read file");
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let reports = report(&input, &policies);
+        let comparison = compare(&reports, policies.len());
+        if json {
+            let failures = report_format.map(|_| failures_json(&reports, &names));
+            let comparison = compare_format.map(|_| comparison_json(&comparison, &names));
+            match (failures, comparison) {
+                (Some(failures), Some(comparison)) => {
+                    println!(r#"{{"failures":{},"comparison":{}}}"#, failures, comparison)
+                }
+                (Some(json), None) | (None, Some(json)) => println!("{}", json),
+                (None, None) => (),
+            }
+        } else {
+            if report_format.is_some() {
+                print!("{}", failures_table(&reports, &names));
+            }
+            if compare_format.is_some() {
+                print!("{}", comparison_table(&comparison, &names));
+            }
+        }
+        return;
     }
 
-    #[test]
-    fn test_invalid() {
-        let line = "8-9 n: nnnnn";
-        assert_eq!(is_password_valid1(line), Some(false));
+    let tally = validate_stream(reader, &policies, stream_config).expect("Failed to read file");
+    for (name, valid_count) in names.iter().zip(tally.valid.iter()) {
+        println!("Valid passwords ({}): {}", name, valid_count);
     }
 }
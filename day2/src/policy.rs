@@ -0,0 +1,489 @@
+use super::Record;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Whether a record's password is acceptable. The occurrence range and
+// position policies read their parameters from the record, the others carry
+// their own.
+pub trait PasswordPolicy: Send + Sync {
+    // Ok if the password is acceptable, otherwise the reason it is not.
+    fn verify(&self, record: &Record) -> Result<(), String>;
+
+    // The policy in the policy language.
+    fn describe(&self) -> String;
+
+    fn check(&self, record: &Record) -> bool {
+        self.verify(record).is_ok()
+    }
+}
+
+impl<P: PasswordPolicy + ?Sized> PasswordPolicy for Box<P> {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        (**self).verify(record)
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+// The needle occurs between first and second times, inclusive. Occurrences
+// of longer needles do not overlap.
+pub struct OccurrenceRange;
+
+impl PasswordPolicy for OccurrenceRange {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let count = record.password.matches(record.needle.as_str()).count();
+        if record.first <= count && count <= record.second {
+            Ok(())
+        } else {
+            Err(format!(
+                "needle '{}' occurs {} times, allowed {}-{}",
+                record.needle, count, record.first, record.second
+            ))
+        }
+    }
+
+    fn describe(&self) -> String {
+        String::from("range")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexMode {
+    Byte,
+    Char,
+    // Extended grapheme clusters, what a reader would call a character.
+    Grapheme,
+}
+
+impl IndexMode {
+    pub const ALL: [IndexMode; 3] = [IndexMode::Byte, IndexMode::Char, IndexMode::Grapheme];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IndexMode::Byte => "byte",
+            IndexMode::Char => "char",
+            IndexMode::Grapheme => "grapheme",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<IndexMode> {
+        IndexMode::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.name() == name)
+    }
+
+    // The byte offsets where two positions start, counting from 1, found in
+    // a single pass over the password. None for a position outside of the
+    // password. A byte position may fall inside a character.
+    fn offsets(
+        self,
+        password: &str,
+        first: usize,
+        second: usize,
+    ) -> (Option<usize>, Option<usize>) {
+        let last = first.max(second);
+        let starts: Box<dyn Iterator<Item = usize>> = match self {
+            IndexMode::Byte => {
+                let offset = |position: usize| {
+                    position
+                        .checked_sub(1)
+                        .filter(|offset| *offset < password.len())
+                };
+                return (offset(first), offset(second));
+            }
+            IndexMode::Char => Box::new(password.char_indices().map(|(offset, _)| offset)),
+            IndexMode::Grapheme => {
+                Box::new(password.grapheme_indices(true).map(|(offset, _)| offset))
+            }
+        };
+        let (mut first_offset, mut second_offset) = (None, None);
+        for (index, offset) in starts.take(last).enumerate() {
+            if index + 1 == first {
+                first_offset = Some(offset);
+            }
+            if index + 1 == second {
+                second_offset = Some(offset);
+            }
+        }
+        (first_offset, second_offset)
+    }
+}
+
+// The needle starts at exactly one of the positions first and second,
+// counting from 1 in the given unit. Both positions must be inside the
+// password.
+pub struct PositionXor(pub IndexMode);
+
+impl PasswordPolicy for PositionXor {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let password = &record.password;
+        // Counting graphemes, the needle has to cover whole graphemes, so
+        // that "e" is not found at the start of an "e" with an accent.
+        let needle = record.needle.as_str();
+        let at = |offset: usize| match password.get(offset..) {
+            Some(rest) if self.0 == IndexMode::Grapheme => rest
+                .graphemes(true)
+                .take(needle.graphemes(true).count())
+                .eq(needle.graphemes(true)),
+            Some(rest) => rest.starts_with(needle),
+            None => false,
+        };
+        let (first, second) = match self.0.offsets(password, record.first, record.second) {
+            (Some(first), Some(second)) => (first, second),
+            (None, _) => return Err(format!("position {} is outside the password", record.first)),
+            (_, None) => {
+                return Err(format!(
+                    "position {} is outside the password",
+                    record.second
+                ))
+            }
+        };
+        match (at(first), at(second)) {
+            (true, true) => Err(format!(
+                "needle '{}' is at both positions {} and {}",
+                record.needle, record.first, record.second
+            )),
+            (false, false) => Err(format!(
+                "needle '{}' is at neither position {} nor {}",
+                record.needle, record.first, record.second
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.0 {
+            IndexMode::Char => String::from("position"),
+            mode => format!("position({})", mode.name()),
+        }
+    }
+}
+
+// Applies a policy to the record with needle and password in lower case.
+pub struct CaseInsensitive<P: PasswordPolicy>(pub P);
+
+impl<P: PasswordPolicy> PasswordPolicy for CaseInsensitive<P> {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let lowered = Record {
+            needle: record.needle.to_lowercase(),
+            password: record.password.to_lowercase(),
+            ..record.clone()
+        };
+        self.0
+            .verify(&lowered)
+            .map_err(|reason| format!("ignoring case, {}", reason))
+    }
+
+    fn describe(&self) -> String {
+        format!("nocase({})", self.0.describe())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    // Anything that is not a letter, a digit or white space.
+    Symbol,
+}
+
+impl CharClass {
+    pub const ALL: [CharClass; 4] = [
+        CharClass::Lower,
+        CharClass::Upper,
+        CharClass::Digit,
+        CharClass::Symbol,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CharClass::Lower => "lower",
+            CharClass::Upper => "upper",
+            CharClass::Digit => "digit",
+            CharClass::Symbol => "symbol",
+        }
+    }
+
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            CharClass::Lower => c.is_lowercase(),
+            CharClass::Upper => c.is_uppercase(),
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+}
+
+// At least min characters of the class.
+pub struct MinClassCount {
+    pub class: CharClass,
+    pub min: usize,
+}
+
+impl PasswordPolicy for MinClassCount {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let count = record
+            .password
+            .chars()
+            .filter(|c| self.class.contains(*c))
+            .count();
+        if count >= self.min {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} {} characters, at least {} required",
+                count,
+                self.class.name(),
+                self.min
+            ))
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("min({}, {})", self.class.name(), self.min)
+    }
+}
+
+// None of the substrings occur.
+pub struct Forbidden(pub Vec<String>);
+
+impl PasswordPolicy for Forbidden {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        match self
+            .0
+            .iter()
+            .find(|substring| record.password.contains(substring.as_str()))
+        {
+            Some(substring) => Err(format!("contains forbidden {:?}", substring)),
+            None => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let substrings: Vec<String> = self.0.iter().map(|s| format!("{:?}", s)).collect();
+        format!("forbid({})", substrings.join(", "))
+    }
+}
+
+fn describe_all(policies: &[Box<dyn PasswordPolicy>], operator: &str) -> String {
+    let policies: Vec<String> = policies.iter().map(|policy| policy.describe()).collect();
+    format!("({})", policies.join(operator))
+}
+
+pub struct All(pub Vec<Box<dyn PasswordPolicy>>);
+
+impl PasswordPolicy for All {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let reasons: Vec<String> = self
+            .0
+            .iter()
+            .filter_map(|policy| policy.verify(record).err())
+            .collect();
+        if reasons.is_empty() {
+            Ok(())
+        } else {
+            Err(reasons.join("; "))
+        }
+    }
+
+    fn describe(&self) -> String {
+        describe_all(&self.0, " and ")
+    }
+}
+
+pub struct Any(pub Vec<Box<dyn PasswordPolicy>>);
+
+impl PasswordPolicy for Any {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        let mut reasons: Vec<String> = Vec::new();
+        for policy in self.0.iter() {
+            match policy.verify(record) {
+                Ok(()) => return Ok(()),
+                Err(reason) => reasons.push(reason),
+            }
+        }
+        Err(format!("no alternative holds: {}", reasons.join("; ")))
+    }
+
+    fn describe(&self) -> String {
+        describe_all(&self.0, " or ")
+    }
+}
+
+pub struct Not(pub Box<dyn PasswordPolicy>);
+
+impl PasswordPolicy for Not {
+    fn verify(&self, record: &Record) -> Result<(), String> {
+        match self.0.verify(record) {
+            Ok(()) => Err(format!("{} holds", self.0.describe())),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("not {}", self.0.describe())
+    }
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use super::*;
+
+    fn record(line: &str) -> Record {
+        Record::parse(line).unwrap()
+    }
+
+    #[test]
+    fn test_puzzle_policies() {
+        assert!(OccurrenceRange.check(&record("1-3 a: abcde")));
+        assert!(!OccurrenceRange.check(&record("1-3 b: cdefg")));
+        assert!(PositionXor(IndexMode::Char).check(&record("1-3 a: abcde")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("1-3 b: cdefg")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("2-9 c: ccccccccc")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("0-9 c: c")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("1-5 a: abc")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("5-1 a: abc")));
+    }
+
+    #[test]
+    fn test_multi_character_needles() {
+        assert!(OccurrenceRange.check(&record("2-2 ab: abxab")));
+        assert!(OccurrenceRange.check(&record("3-3 aa: aaaaaa")));
+        assert!(PositionXor(IndexMode::Char).check(&record("2-4 ab: xabxx")));
+        assert!(!PositionXor(IndexMode::Char).check(&record("2-3 ab: xaax")));
+        assert!(PositionXor(IndexMode::Char).check(&record("2-3 åb: xåbb")));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        assert!(!OccurrenceRange.check(&record("2-3 a: AaB")));
+        assert!(CaseInsensitive(OccurrenceRange).check(&record("2-3 a: AaB")));
+        assert!(CaseInsensitive(PositionXor(IndexMode::Char)).check(&record("1-2 A: ab")));
+    }
+
+    #[test]
+    fn test_min_class_count() {
+        let digits = MinClassCount {
+            class: CharClass::Digit,
+            min: 2,
+        };
+        assert!(digits.check(&record("1-1 a: a1b2")));
+        assert!(!digits.check(&record("1-1 a: a1b")));
+        let symbols = MinClassCount {
+            class: CharClass::Symbol,
+            min: 1,
+        };
+        assert!(symbols.check(&record("1-1 a: a!")));
+        assert!(!symbols.check(&record("1-1 a: a b")));
+        assert!(CharClass::Upper.contains('Å'));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let forbidden = Forbidden(vec![String::from("123"), String::from("pass")]);
+        assert!(!forbidden.check(&record("1-1 a: mypassword")));
+        assert!(forbidden.check(&record("1-1 a: hunter2")));
+
+        let policy = All(vec![
+            Box::new(Any(vec![
+                Box::new(OccurrenceRange),
+                Box::new(PositionXor(IndexMode::Char)),
+            ])),
+            Box::new(Not(Box::new(forbidden))),
+        ]);
+        assert!(policy.check(&record("1-3 a: a123")));
+        assert!(!policy.check(&record("1-3 a: abcde")));
+        assert!(!policy.check(&record("4-5 z: a123")));
+        assert!(All(vec![]).check(&record("1-1 a: b")));
+        assert!(!Any(vec![]).check(&record("1-1 a: b")));
+    }
+
+    #[test]
+    fn test_reasons() {
+        let verify = |policy: &dyn PasswordPolicy, line: &str| policy.verify(&record(line));
+        assert_eq!(
+            verify(&OccurrenceRange, "1-3 a: aaaaa"),
+            Err(String::from("needle 'a' occurs 5 times, allowed 1-3"))
+        );
+        assert_eq!(
+            verify(&PositionXor(IndexMode::Char), "2-9 c: ccccccccc"),
+            Err(String::from("needle 'c' is at both positions 2 and 9"))
+        );
+        assert_eq!(
+            verify(&PositionXor(IndexMode::Char), "1-3 b: cdefg"),
+            Err(String::from("needle 'b' is at neither position 1 nor 3"))
+        );
+        assert_eq!(
+            verify(&PositionXor(IndexMode::Char), "1-5 a: abc"),
+            Err(String::from("position 5 is outside the password"))
+        );
+        let digits = MinClassCount {
+            class: CharClass::Digit,
+            min: 3,
+        };
+        assert_eq!(
+            verify(&digits, "1-1 a: a1b2"),
+            Err(String::from("2 digit characters, at least 3 required"))
+        );
+        let forbidden = Forbidden(vec![String::from("12")]);
+        assert_eq!(
+            verify(&forbidden, "1-1 a: a123"),
+            Err(String::from("contains forbidden \"12\""))
+        );
+        let policy = Any(vec![
+            Box::new(Not(Box::new(Forbidden(vec![String::from("x")])))),
+            Box::new(CaseInsensitive(OccurrenceRange)),
+        ]);
+        assert_eq!(policy.describe(), "(not forbid(\"x\") or nocase(range))");
+        assert_eq!(
+            verify(&policy, "1-1 a: AaB"),
+            Err(String::from(
+                "no alternative holds: forbid(\"x\") holds; \
+                 ignoring case, needle 'a' occurs 2 times, allowed 1-1"
+            ))
+        );
+        let policy = All(vec![
+            Box::new(OccurrenceRange),
+            Box::new(PositionXor(IndexMode::Char)),
+        ]);
+        assert_eq!(
+            verify(&policy, "1-2 a: bb"),
+            Err(String::from(
+                "needle 'a' occurs 0 times, allowed 1-2; \
+                 needle 'a' is at neither position 1 nor 2"
+            ))
+        );
+        assert_eq!(verify(&policy, "1-2 a: ab"), Ok(()));
+    }
+
+    #[test]
+    fn test_index_modes() {
+        // "e" followed by a combining acute accent is one grapheme but two
+        // chars, and three bytes.
+        let check = |mode: IndexMode, line: &str| PositionXor(mode).check(&record(line));
+        let line = "2-4 x: e\u{301}xex";
+        assert!(check(IndexMode::Byte, line));
+        assert!(!check(IndexMode::Char, line));
+        assert!(!check(IndexMode::Grapheme, line));
+        let line = "1-2 x: e\u{301}x";
+        assert!(!check(IndexMode::Byte, line));
+        assert!(!check(IndexMode::Char, line));
+        assert!(check(IndexMode::Grapheme, line));
+        // Counting graphemes the needle e does not match the accented e.
+        let line = "1-2 e: e\u{301}x";
+        assert!(check(IndexMode::Char, line));
+        assert!(!check(IndexMode::Grapheme, line));
+        assert!(check(IndexMode::Grapheme, "1-2 e\u{301}: e\u{301}x"));
+        assert!(check(IndexMode::Grapheme, "2-3 xe: xxe"));
+        assert!(!check(IndexMode::Grapheme, "2-3 xe: xxe\u{301}"));
+        let line = "4-5 x: åaxb";
+        assert!(PositionXor(IndexMode::Byte).check(&record(line)));
+        // Byte 2 is inside the å, and never matches.
+        assert!(PositionXor(IndexMode::Byte).check(&record("2-3 \u{a5}: \u{e5}\u{a5}")));
+        assert_eq!(PositionXor(IndexMode::Byte).describe(), "position(byte)");
+        assert_eq!(IndexMode::parse("grapheme"), Some(IndexMode::Grapheme));
+    }
+}
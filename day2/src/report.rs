@@ -0,0 +1,207 @@
+use super::policy::PasswordPolicy;
+use super::{Record, RecordError};
+use std::fmt::Write;
+
+// What became of each line of the database under a set of policies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineReport {
+    // Counted from 1.
+    pub line: usize,
+    pub text: String,
+    pub record: Result<Record, RecordError>,
+    // One result per policy, in order, empty if the line did not parse.
+    pub results: Vec<Result<(), String>>,
+}
+
+impl LineReport {
+    pub fn failed(&self) -> bool {
+        self.record.is_err() || self.results.iter().any(|result| result.is_err())
+    }
+
+    // The policy index and reason of every failure.
+    pub fn failures(&self) -> Vec<(usize, &str)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| match result {
+                Err(reason) => Some((index, reason.as_str())),
+                Ok(()) => None,
+            })
+            .collect()
+    }
+}
+
+// Blank lines are not records and are left out.
+pub fn report(input: &str, policies: &[&dyn PasswordPolicy]) -> Vec<LineReport> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(index, text)| {
+            let record = Record::parse(text);
+            let results = match &record {
+                Ok(record) => policies
+                    .iter()
+                    .map(|policy| policy.verify(record))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            LineReport {
+                line: index + 1,
+                text: String::from(text),
+                record,
+                results,
+            }
+        })
+        .collect()
+}
+
+// One row per failure. Lines that do not parse get a row without a policy.
+pub fn failures_table(reports: &[LineReport], names: &[&str]) -> String {
+    let mut rows: Vec<(String, &str, String)> = Vec::new();
+    for report in reports.iter() {
+        if let Err(error) = &report.record {
+            rows.push((report.line.to_string(), "-", error.to_string()));
+        }
+        for (index, reason) in report.failures() {
+            rows.push((report.line.to_string(), names[index], String::from(reason)));
+        }
+    }
+    let line_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(4);
+    let policy_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(6);
+
+    let mut table = String::new();
+    writeln!(
+        table,
+        "{:>line$}  {:policy$}  reason",
+        "line",
+        "policy",
+        line = line_width,
+        policy = policy_width
+    )
+    .unwrap();
+    for (line, policy, reason) in rows.iter() {
+        writeln!(
+            table,
+            "{:>line$}  {:policy$}  {}",
+            line,
+            policy,
+            reason,
+            line = line_width,
+            policy = policy_width
+        )
+        .unwrap();
+    }
+    table
+}
+
+// An array with an object per failing line.
+pub fn failures_json(reports: &[LineReport], names: &[&str]) -> String {
+    let lines: Vec<String> = reports
+        .iter()
+        .filter(|report| report.failed())
+        .map(|report| {
+            let outcome = match &report.record {
+                Err(error) => format!(r#""error":{}"#, json_string(&error.to_string())),
+                Ok(_) => {
+                    let failures: Vec<String> = report
+                        .failures()
+                        .iter()
+                        .map(|(index, reason)| {
+                            format!(
+                                r#"{{"policy":{},"reason":{}}}"#,
+                                json_string(names[*index]),
+                                json_string(reason)
+                            )
+                        })
+                        .collect();
+                    format!(r#""failures":[{}]"#, failures.join(","))
+                }
+            };
+            format!(
+                r#"{{"line":{},"text":{},{}}}"#,
+                report.line,
+                json_string(&report.text),
+                outcome
+            )
+        })
+        .collect();
+    format!("[{}]", lines.join(","))
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+    use crate::policy::{IndexMode, OccurrenceRange, PositionXor};
+
+    const EXAMPLE: &str = "1-3 a: abcde
+1-3 b: cdefg
+
+2-9 c: ccccccccc
+3 c: \"c\"
+";
+
+    #[test]
+    fn test_report() {
+        let reports = report(EXAMPLE, &[&OccurrenceRange, &PositionXor(IndexMode::Char)]);
+        let lines: Vec<usize> = reports.iter().map(|report| report.line).collect();
+        assert_eq!(lines, vec![1, 2, 4, 5]);
+        assert!(!reports[0].failed());
+        assert_eq!(
+            reports[1].failures(),
+            vec![
+                (0, "needle 'b' occurs 0 times, allowed 1-3"),
+                (1, "needle 'b' is at neither position 1 nor 3")
+            ]
+        );
+        assert_eq!(reports[2].results[0], Ok(()));
+        assert_eq!(reports[3].record, Err(RecordError::Malformed));
+        assert!(reports[3].results.is_empty());
+        assert!(reports[3].failed());
+    }
+
+    #[test]
+    fn test_failures_table() {
+        let reports = report(EXAMPLE, &[&OccurrenceRange, &PositionXor(IndexMode::Char)]);
+        assert_eq!(
+            failures_table(&reports, &["sled", "toboggan"]),
+            "line  policy    reason
+   2  sled      needle 'b' occurs 0 times, allowed 1-3
+   2  toboggan  needle 'b' is at neither position 1 nor 3
+   4  toboggan  needle 'c' is at both positions 2 and 9
+   5  -         expected a line like \"1-3 a: abcde\"
+"
+        );
+    }
+
+    #[test]
+    fn test_failures_json() {
+        let reports = report(EXAMPLE, &[&OccurrenceRange]);
+        assert_eq!(
+            failures_json(&reports, &["sled"]),
+            concat!(
+                r#"[{"line":2,"text":"1-3 b: cdefg","failures":"#,
+                r#"[{"policy":"sled","reason":"needle 'b' occurs 0 times, allowed 1-3"}]},"#,
+                r#"{"line":5,"text":"3 c: \"c\"","error":"expected a line like \"1-3 a: abcde\""}]"#
+            )
+        );
+        assert_eq!(failures_json(&[], &[]), "[]");
+    }
+}
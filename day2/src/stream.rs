@@ -0,0 +1,203 @@
+use super::Record;
+use crate::policy::PasswordPolicy;
+use std::io::{self, BufRead};
+use std::thread;
+
+// How many lines are read before they are validated, and by how many threads.
+// Memory use is bounded by the chunk, however large the input is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamConfig {
+    pub chunk_lines: usize,
+    pub threads: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> StreamConfig {
+        StreamConfig {
+            chunk_lines: 64 * 1024,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn with_chunk_lines(self, chunk_lines: usize) -> StreamConfig {
+        StreamConfig {
+            chunk_lines: chunk_lines.max(1),
+            ..self
+        }
+    }
+
+    pub fn with_threads(self, threads: usize) -> StreamConfig {
+        StreamConfig {
+            threads: threads.max(1),
+            ..self
+        }
+    }
+}
+
+// Counts over a whole database. Blank lines are not counted at all, lines
+// that do not parse or are not UTF-8 count as malformed, and valid has one
+// count per policy.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tally {
+    pub lines: usize,
+    pub malformed: usize,
+    pub valid: Vec<usize>,
+}
+
+impl Tally {
+    fn new(policy_count: usize) -> Tally {
+        Tally {
+            lines: 0,
+            malformed: 0,
+            valid: vec![0; policy_count],
+        }
+    }
+
+    fn add(&mut self, other: &Tally) {
+        self.lines += other.lines;
+        self.malformed += other.malformed;
+        for (valid, other_valid) in self.valid.iter_mut().zip(other.valid.iter()) {
+            *valid += other_valid;
+        }
+    }
+}
+
+fn validate_lines(lines: &[String], policies: &[&dyn PasswordPolicy]) -> Tally {
+    let mut tally = Tally::new(policies.len());
+    for line in lines {
+        tally.lines += 1;
+        match Record::parse(line) {
+            Ok(record) => {
+                for (valid, policy) in tally.valid.iter_mut().zip(policies.iter()) {
+                    if policy.check(&record) {
+                        *valid += 1;
+                    }
+                }
+            }
+            Err(_) => tally.malformed += 1,
+        }
+    }
+    tally
+}
+
+fn validate_chunk(lines: &[String], policies: &[&dyn PasswordPolicy], threads: usize) -> Tally {
+    if threads <= 1 || lines.len() <= 1 {
+        return validate_lines(lines, policies);
+    }
+    let part_size = lines.len().div_ceil(threads);
+    let mut tally = Tally::new(policies.len());
+    thread::scope(|scope| {
+        let parts: Vec<_> = lines
+            .chunks(part_size)
+            .map(|part| scope.spawn(move || validate_lines(part, policies)))
+            .collect();
+        for part in parts {
+            tally.add(&part.join().expect("Validation thread panicked"));
+        }
+    });
+    tally
+}
+
+// Validates a password database line by line, a chunk at a time, without
+// holding more than one chunk in memory.
+pub fn validate_stream<R: BufRead>(
+    mut reader: R,
+    policies: &[&dyn PasswordPolicy],
+    config: StreamConfig,
+) -> io::Result<Tally> {
+    let mut tally = Tally::new(policies.len());
+    let mut buffer = Vec::new();
+    let mut chunk: Vec<String> = Vec::with_capacity(config.chunk_lines);
+    loop {
+        buffer.clear();
+        let done = reader.read_until(b'\n', &mut buffer)? == 0;
+        if !done {
+            let mut line = buffer.as_slice();
+            line = line.strip_suffix(b"\n").unwrap_or(line);
+            line = line.strip_suffix(b"\r").unwrap_or(line);
+            match std::str::from_utf8(line) {
+                // Blank lines are not records, as in report.
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => chunk.push(String::from(line)),
+                Err(_) => {
+                    tally.lines += 1;
+                    tally.malformed += 1;
+                }
+            }
+        }
+        if chunk.len() >= config.chunk_lines || (done && !chunk.is_empty()) {
+            tally.add(&validate_chunk(&chunk, policies, config.threads));
+            chunk.clear();
+        }
+        if done {
+            return Ok(tally);
+        }
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::count_valid_passwords;
+    use crate::policy::{IndexMode, OccurrenceRange, PositionXor};
+    use crate::report::report;
+
+    const EXAMPLE: &str = "1-3 a: abcde\r\n1-3 b: cdefg\n\n2-9 c: ccccccccc\nnonsense\n1-2 å: åå";
+
+    #[test]
+    fn test_matches_count_valid_passwords() {
+        let first = OccurrenceRange;
+        let second = PositionXor(IndexMode::Char);
+        let policies: Vec<&dyn PasswordPolicy> = vec![&first, &second];
+        for chunk_lines in 1..5 {
+            for threads in 1..4 {
+                let config = StreamConfig::default()
+                    .with_chunk_lines(chunk_lines)
+                    .with_threads(threads);
+                let tally = validate_stream(EXAMPLE.as_bytes(), &policies, config).unwrap();
+                assert_eq!(tally.lines, 5);
+                assert_eq!(tally.malformed, 1);
+                let expected: Vec<usize> = policies
+                    .iter()
+                    .map(|policy| count_valid_passwords(EXAMPLE, *policy))
+                    .collect();
+                assert_eq!(tally.valid, expected);
+                assert_eq!(tally.valid, vec![3, 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blank_lines_match_report() {
+        let input = "1-3 a: abcde\n   \n\t\r\n1-3 b: cdefg\n \u{a0}\n";
+        let policies: Vec<&dyn PasswordPolicy> = vec![&OccurrenceRange];
+        let tally = validate_stream(input.as_bytes(), &policies, StreamConfig::default()).unwrap();
+        assert_eq!(tally.lines, report(input, &policies).len());
+        assert_eq!(tally.lines, 2);
+        assert_eq!(tally.malformed, 0);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let input: &[u8] = b"1-3 a: abcde\n1-3 a: \xff\xfea\n";
+        let policies: Vec<&dyn PasswordPolicy> = vec![&OccurrenceRange];
+        let tally = validate_stream(input, &policies, StreamConfig::default()).unwrap();
+        assert_eq!(
+            tally,
+            Tally {
+                lines: 2,
+                malformed: 1,
+                valid: vec![1],
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let policies: Vec<&dyn PasswordPolicy> = vec![&OccurrenceRange];
+        let tally = validate_stream(&b""[..], &policies, StreamConfig::default()).unwrap();
+        assert_eq!(tally, Tally::new(1));
+    }
+}
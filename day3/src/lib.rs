@@ -0,0 +1,312 @@
+use std::error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapError {
+    // No line starts with an open square or a tree.
+    Empty,
+    // A row is not as wide as the first one. The line is counted from 1.
+    Ragged {
+        line: usize,
+        width: usize,
+        expected: usize,
+    },
+    // A slope that never moves down never leaves the map.
+    FlatSlope,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Empty => write!(f, "the map has no rows"),
+            MapError::Ragged {
+                line,
+                width,
+                expected,
+            } => write!(
+                f,
+                "line {}: row is {} squares wide, expected {}",
+                line, width, expected
+            ),
+            MapError::FlatSlope => write!(f, "the slope must move down or up"),
+        }
+    }
+}
+
+impl error::Error for MapError {}
+
+// What happens when the toboggan moves past the left or right edge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Horizontal {
+    // The pattern repeats, as in the puzzle.
+    Wrap,
+    // The toboggan stays at the edge.
+    Clamp,
+    // The descent ends.
+    Stop,
+}
+
+impl Horizontal {
+    pub const ALL: [Horizontal; 3] = [Horizontal::Wrap, Horizontal::Clamp, Horizontal::Stop];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Horizontal::Wrap => "wrap",
+            Horizontal::Clamp => "clamp",
+            Horizontal::Stop => "stop",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Horizontal> {
+        Horizontal::ALL
+            .iter()
+            .copied()
+            .find(|horizontal| horizontal.name() == name)
+    }
+}
+
+// Squares moved right and down per step, negative to move left or up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slope {
+    pub dx: i64,
+    pub dy: i64,
+}
+
+impl Slope {
+    pub fn new(dx: i64, dy: i64) -> Slope {
+        Slope { dx, dy }
+    }
+}
+
+// The squares visited on the way down, starting at the top left, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Descent {
+    pub visited: Vec<(usize, usize)>,
+    pub hits: Vec<(usize, usize)>,
+}
+
+impl Descent {
+    pub fn trees(&self) -> usize {
+        self.hits.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeMap {
+    width: usize,
+    height: usize,
+    trees: Vec<bool>,
+}
+
+impl TreeMap {
+    // Each row is the run of '.' and '#' a line starts with, anything after
+    // it is ignored. Lines without such a run are skipped.
+    pub fn parse(input: &str) -> Result<TreeMap, MapError> {
+        let mut width = None;
+        let mut height = 0;
+        let mut trees = Vec::new();
+        for (index, line) in input.lines().enumerate() {
+            let row: Vec<bool> = line
+                .chars()
+                .take_while(|c| *c == '.' || *c == '#')
+                .map(|c| c == '#')
+                .collect();
+            if row.is_empty() {
+                continue;
+            }
+            let expected = *width.get_or_insert(row.len());
+            if row.len() != expected {
+                return Err(MapError::Ragged {
+                    line: index + 1,
+                    width: row.len(),
+                    expected,
+                });
+            }
+            trees.extend(row);
+            height += 1;
+        }
+        Ok(TreeMap {
+            width: width.ok_or(MapError::Empty)?,
+            height,
+            trees,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_tree(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.trees[y * self.width + x]
+    }
+
+    // Follows the slope from the top left until the toboggan leaves the
+    // top or bottom of the map, or a side when stopping there.
+    pub fn descend(&self, slope: Slope, horizontal: Horizontal) -> Result<Descent, MapError> {
+        if slope.dy == 0 {
+            return Err(MapError::FlatSlope);
+        }
+        let (width, height) = (self.width as i128, self.height as i128);
+        let (mut x, mut y) = (0i128, 0i128);
+        let mut descent = Descent {
+            visited: Vec::new(),
+            hits: Vec::new(),
+        };
+        while (0..height).contains(&y) {
+            let position = (x as usize, y as usize);
+            descent.visited.push(position);
+            if self.is_tree(position.0, position.1) {
+                descent.hits.push(position);
+            }
+
+            x += slope.dx as i128;
+            y += slope.dy as i128;
+            match horizontal {
+                Horizontal::Wrap => x = x.rem_euclid(width),
+                Horizontal::Clamp => x = x.clamp(0, width - 1),
+                Horizontal::Stop if !(0..width).contains(&x) => break,
+                Horizontal::Stop => (),
+            }
+        }
+        Ok(descent)
+    }
+}
+
+#[cfg(test)]
+mod tests3 {
+    use super::*;
+
+    const EXAMPLE: &str = r"
+..##.........##.........##.........##.........##.........##.......  --->
+#...#...#..#...#...#..#...#...#..#...#...#..#...#...#..#...#...#..
+.#....#..#..#....#..#..#....#..#..#....#..#..#....#..#..#....#..#.
+..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#
+.#...##..#..#...##..#..#...##..#..#...##..#..#...##..#..#...##..#.
+..#.##.......#.##.......#.##.......#.##.......#.##.......#.##.....  --->
+.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#
+.#........#.#........#.#........#.#........#.#........#.#........#
+#.##...#...#.##...#...#.##...#...#.##...#...#.##...#...#.##...#...
+#...##....##...##....##...##....##...##....##...##....##...##....#
+.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#  --->
+";
+
+    // The first eleven columns of the example, which repeat.
+    const TILE: &str = "..##.......
+#...#...#..
+.#....#..#.
+..#.#...#.#
+.#...##..#.
+..#.##.....
+.#.#.#....#
+.#........#
+#.##...#...
+#...##....#
+.#..#...#.#
+";
+
+    #[test]
+    fn test_example1_1() -> Result<(), MapError> {
+        let descent = TreeMap::parse(EXAMPLE)?.descend(Slope::new(1, 1), Horizontal::Wrap)?;
+        assert_eq!(descent.trees(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_example3_1() -> Result<(), MapError> {
+        let descent = TreeMap::parse(EXAMPLE)?.descend(Slope::new(3, 1), Horizontal::Wrap)?;
+        assert_eq!(descent.trees(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_example5_1() -> Result<(), MapError> {
+        let descent = TreeMap::parse(EXAMPLE)?.descend(Slope::new(5, 1), Horizontal::Wrap)?;
+        assert_eq!(descent.trees(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_example7_1() -> Result<(), MapError> {
+        let descent = TreeMap::parse(EXAMPLE)?.descend(Slope::new(7, 1), Horizontal::Wrap)?;
+        assert_eq!(descent.trees(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_example1_2() -> Result<(), MapError> {
+        let descent = TreeMap::parse(EXAMPLE)?.descend(Slope::new(1, 2), Horizontal::Wrap)?;
+        assert_eq!(descent.trees(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() {
+        let map = TreeMap::parse(TILE).unwrap();
+        assert_eq!((map.width(), map.height()), (11, 11));
+        assert!(map.is_tree(2, 0));
+        assert!(!map.is_tree(0, 0));
+        assert!(!map.is_tree(11, 1));
+        assert_eq!(TreeMap::parse(EXAMPLE).unwrap().width(), 66);
+        assert_eq!(TreeMap::parse("\n  \n"), Err(MapError::Empty));
+        assert_eq!(
+            TreeMap::parse("..#\n.#\n"),
+            Err(MapError::Ragged {
+                line: 2,
+                width: 2,
+                expected: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrap() {
+        let map = TreeMap::parse(TILE).unwrap();
+        for (dx, dy, trees) in [(1, 1, 2), (3, 1, 7), (5, 1, 3), (7, 1, 4), (1, 2, 2)] {
+            let descent = map.descend(Slope::new(dx, dy), Horizontal::Wrap).unwrap();
+            assert_eq!(descent.trees(), trees);
+        }
+        let descent = map.descend(Slope::new(3, 1), Horizontal::Wrap).unwrap();
+        assert_eq!(descent.visited.len(), 11);
+        assert_eq!(
+            &descent.visited[..5],
+            &[(0, 0), (3, 1), (6, 2), (9, 3), (1, 4)]
+        );
+        assert_eq!(descent.hits[0], (6, 2));
+
+        // Going left by 8 wraps the same as going right by 3.
+        let left = map.descend(Slope::new(-8, 1), Horizontal::Wrap).unwrap();
+        assert_eq!(left, descent);
+        let far = map
+            .descend(Slope::new(i64::MIN, 1), Horizontal::Wrap)
+            .unwrap();
+        assert_eq!(far.visited.len(), 11);
+    }
+
+    #[test]
+    fn test_clamp_and_stop() {
+        let map = TreeMap::parse(TILE).unwrap();
+        let clamped = map.descend(Slope::new(5, 3), Horizontal::Clamp).unwrap();
+        assert_eq!(clamped.visited, vec![(0, 0), (5, 3), (10, 6), (10, 9)]);
+        assert_eq!(clamped.hits, vec![(10, 6), (10, 9)]);
+        let clamped = map.descend(Slope::new(-1, 1), Horizontal::Clamp).unwrap();
+        assert!(clamped.visited.iter().all(|(x, _)| *x == 0));
+        assert_eq!(clamped.trees(), 3);
+
+        let stopped = map.descend(Slope::new(5, 3), Horizontal::Stop).unwrap();
+        assert_eq!(stopped.visited, vec![(0, 0), (5, 3), (10, 6)]);
+        let stopped = map.descend(Slope::new(-1, 1), Horizontal::Stop).unwrap();
+        assert_eq!(stopped.visited, vec![(0, 0)]);
+
+        let upwards = map.descend(Slope::new(1, -1), Horizontal::Wrap).unwrap();
+        assert_eq!(upwards.visited, vec![(0, 0)]);
+        assert_eq!(
+            map.descend(Slope::new(1, 0), Horizontal::Wrap),
+            Err(MapError::FlatSlope)
+        );
+        assert_eq!(Horizontal::parse("clamp"), Some(Horizontal::Clamp));
+    }
+}
@@ -1,112 +1,76 @@
+use day3::{Horizontal, Slope, TreeMap};
 use std::env;
 use std::fs;
+use std::process;
 
-struct Pos {
-    x: usize,
-    y: usize,
-}
-
-impl Pos {
-    fn new(x: usize, y: usize) -> Self {
-        Self { x, y }
+fn parse_slope(text: &str) -> Option<Slope> {
+    let mut parts = text.split(',');
+    let dx = parts.next()?.trim().parse().ok()?;
+    let dy = parts.next()?.trim().parse().ok()?;
+    match parts.next() {
+        None => Some(Slope::new(dx, dy)),
+        Some(_) => None,
     }
 }
 
-fn count_trees(map: &str, slope: &Pos) -> usize {
-    let width = map
-        .lines()
-        .find(|l| {
-            if let Some(c) = l.chars().nth(0) {
-                c == '.' || c == '#'
-            } else {
-                false
-            }
-        })
-        .unwrap()
-        .chars()
-        .filter(|c| *c == '.' || *c == '#')
-        .count();
-
-    let clean_map: Vec<char> = map.chars().filter(|c| c == &'.' || c == &'#').collect();
-    let map_len = clean_map.len();
-    let stride = slope.x + width * slope.y;
-    let steps = map_len / stride;
-    let mut positions: Vec<usize> = (0..map_len).step_by(stride).collect();
-    if steps * stride < map_len - width {
-        positions.push((steps + 1) * stride - width);
-    }
-    positions
-        .iter()
-        .filter(|pos| clean_map.get(**pos).unwrap() == &'#')
-        .count()
-}
-
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let filename = args.get(1).expect("Usage: day1 input-filename");
+    let usage = "Usage: day3 input-filename [--slope dx,dy] [--horizontal wrap|clamp|stop]";
+    let filename = args.get(1).expect(usage);
+    let mut slope: Option<Slope> = None;
+    let mut horizontal = Horizontal::Wrap;
+    let mut options = args.iter().skip(2);
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--slope" => slope = Some(options.next().and_then(|s| parse_slope(s)).expect(usage)),
+            "--horizontal" => {
+                horizontal = options
+                    .next()
+                    .and_then(|name| Horizontal::parse(name))
+                    .expect(usage)
+            }
+            _ => panic!("{}", usage),
+        }
+    }
 
     println!("Reading input from {}", filename);
     let input = fs::read_to_string(filename).expect("Failed to read file");
+    let map = TreeMap::parse(&input).unwrap_or_else(|error| {
+        eprintln!("{}: {}", filename, error);
+        process::exit(1);
+    });
+    let descend = |slope: Slope| {
+        map.descend(slope, horizontal).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        })
+    };
+
+    if let Some(slope) = slope {
+        let descent = descend(slope);
+        println!("Visited {}", descent.visited.len());
+        for (x, y) in descent.hits.iter() {
+            println!("Tree at {},{}", x, y);
+        }
+        println!("Trees {}", descent.trees());
+        return;
+    }
 
-    let slope = Pos::new(3, 1);
-    println!("Trees {}", count_trees(&input, &slope));
+    println!("Trees {}", descend(Slope::new(3, 1)).trees());
 
     let mut product: usize = 1;
     for slope in [
-        Pos::new(1, 1),
-        Pos::new(3, 1),
-        Pos::new(5, 1),
-        Pos::new(7, 1),
-        Pos::new(1, 2),
+        Slope::new(1, 1),
+        Slope::new(3, 1),
+        Slope::new(5, 1),
+        Slope::new(7, 1),
+        Slope::new(1, 2),
     ]
     .iter()
     {
-        let trees = count_trees(&input, &slope);
+        let trees = descend(*slope).trees();
         product *= trees;
         println!("Trees {}", trees);
     }
     println!("Product {}", product);
 }
-
-#[cfg(test)]
-mod tests3 {
-    use super::*;
-
-    const EXAMPLE: &str = r"
-..##.........##.........##.........##.........##.........##.......  --->
-#...#...#..#...#...#..#...#...#..#...#...#..#...#...#..#...#...#..
-.#....#..#..#....#..#..#....#..#..#....#..#..#....#..#..#....#..#.
-..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#..#.#...#.#
-.#...##..#..#...##..#..#...##..#..#...##..#..#...##..#..#...##..#.
-..#.##.......#.##.......#.##.......#.##.......#.##.......#.##.....  --->
-.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#.#.#.#....#
-.#........#.#........#.#........#.#........#.#........#.#........#
-#.##...#...#.##...#...#.##...#...#.##...#...#.##...#...#.##...#...
-#...##....##...##....##...##....##...##....##...##....##...##....#
-.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#.#..#...#.#  --->
-";
-    #[test]
-    fn test_example1_1() {
-        assert_eq!(count_trees(EXAMPLE, &Pos::new(1, 1)), 2);
-    }
-
-    #[test]
-    fn test_example3_1() {
-        assert_eq!(count_trees(EXAMPLE, &Pos::new(3, 1)), 7);
-    }
-
-    #[test]
-    fn test_example5_1() {
-        assert_eq!(count_trees(EXAMPLE, &Pos::new(5, 1)), 3);
-    }
-
-    #[test]
-    fn test_example7_1() {
-        assert_eq!(count_trees(EXAMPLE, &Pos::new(7, 1)), 4);
-    }
-
-    #[test]
-    fn test_example1_2() {
-        assert_eq!(count_trees(EXAMPLE, &Pos::new(1, 2)), 2);
-    }
-}
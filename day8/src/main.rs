@@ -1,90 +1,18 @@
-use gameboy;
 use std::env;
 use std::fs;
 
-fn solve_part1(program: &Vec<gameboy::Instruction>) -> Option<i64> {
+fn solve_part1(program: &[gameboy::Instruction]) -> Option<i64> {
     match gameboy::execute_program(program) {
         Ok(accumulator) => Some(accumulator),
-        Err(gameboy::ExitError::InvalidPC(_, _)) => None,
         Err(gameboy::ExitError::InfiniteLoop(accumulator, _)) => Some(accumulator),
+        Err(_) => None,
     }
 }
 
-fn solve_part2(program: &Vec<gameboy::Instruction>) -> Option<(i64, usize)> {
-    let mut next_pos: Option<usize> = program.iter().position(|i| match i {
-        gameboy::Instruction::Nop(_) => true,
-        _ => false,
-    });
-    while next_pos.is_some() {
-        let mut copy = program.clone();
-        let pos = next_pos.unwrap();
-        let arg = match copy.get(pos) {
-            Some(gameboy::Instruction::Nop(arg)) => arg,
-            _ => {
-                panic!("wtf");
-            }
-        };
-        println!(
-            "{}: {:?} -> {:?}",
-            pos,
-            copy[pos],
-            gameboy::Instruction::Jmp(*arg as isize)
-        );
-        copy[pos] = gameboy::Instruction::Jmp(*arg as isize);
-
-        match gameboy::execute_program(&copy) {
-            Ok(accumulator) => return Some((accumulator, pos)),
-            _ => (),
-        }
-        next_pos = program
-            .iter()
-            .skip(pos + 1)
-            .position(|i| match i {
-                gameboy::Instruction::Nop(_) => true,
-                _ => false,
-            })
-            .map(|found_pos| found_pos + pos + 1);
-    }
-
-    let mut next_pos = program.iter().position(|instr| match instr {
-        gameboy::Instruction::Jmp(_) => true,
-        _ => false,
-    });
-    println!("{:?}", next_pos);
-    while next_pos.is_some() {
-        let mut copy = program.clone();
-        let pos = next_pos.unwrap();
-        let arg = match copy.get(pos) {
-            Some(gameboy::Instruction::Jmp(arg)) => arg,
-            Some(x) => {
-                panic!("{}: wtf {:?}", pos, x);
-            }
-            None => {
-                panic!("wtf none");
-            }
-        };
-        println!(
-            "{}: {:?} -> {:?}",
-            pos + 1,
-            copy[pos],
-            gameboy::Instruction::Nop(*arg as i64)
-        );
-        copy[pos] = gameboy::Instruction::Nop(*arg as i64);
-
-        match gameboy::execute_program(&copy) {
-            Ok(accumulator) => return Some((accumulator, pos)),
-            _ => (),
-        }
-        next_pos = program
-            .iter()
-            .skip(pos + 1)
-            .position(|i| match i {
-                gameboy::Instruction::Jmp(_) => true,
-                _ => false,
-            })
-            .map(|found_pos| found_pos + pos + 1);
-    }
-    None
+fn solve_part2(program: &[gameboy::Instruction]) -> Option<(i64, usize)> {
+    gameboy::find_repairs(program, &[gameboy::Mutation::SwapNopJmp])
+        .first()
+        .map(|repair| (repair.accumulator, repair.pc))
 }
 
 fn main() {
@@ -96,13 +24,10 @@ fn main() {
 
     let program = match gameboy::parse_program(&input) {
         Ok(program) => program,
-        Err(line_index) => {
-            println!(
-                "error: {}|{}| Failed to parse {}",
-                filename,
-                line_index + 1,
-                input.lines().nth(line_index).unwrap()
-            );
+        Err(errors) => {
+            for error in errors {
+                println!("error: {}:{}", filename, error);
+            }
             return;
         }
     };
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    gameboy::fuzz::check_execute(data);
+    gameboy::fuzz::check_decoded(data);
+});
@@ -0,0 +1,692 @@
+use super::{program_dialect, Dialect, Instruction, Operand, Register};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+// Source syntax, one statement per line:
+//
+//   loop:           # a label, refers to the next instruction
+//   acc +1          # comments run to the end of the line
+//   jmp loop        # jmp and nop take a literal offset or a label
+//
+// After a `.dialect 2` directive, ahead of the first instruction:
+//
+//   set a +3        # registers acc, a, b, c and d
+//   add a b         # sources are a literal or a register, also for mul
+//   jnz a loop      # jz and jnz test a register, then jump like jmp
+//   inp b           # read from the input channel
+//   out acc         # write to the output channel
+//
+// Labels are resolved to offsets relative to the referencing instruction.
+// A label after the last instruction refers to the exit position.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnknownOpcode,
+    MissingOpcode,
+    MissingOperand,
+    MalformedNumber,
+    Overflow,
+    UnexpectedToken,
+    UndefinedLabel,
+    DuplicateLabel,
+    InvalidRegister,
+    UnknownDialect,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ParseErrorKind::UnknownOpcode => "unknown opcode",
+            ParseErrorKind::MissingOpcode => "missing opcode",
+            ParseErrorKind::MissingOperand => "missing operand",
+            ParseErrorKind::MalformedNumber => "malformed number",
+            ParseErrorKind::Overflow => "number out of range",
+            ParseErrorKind::UnexpectedToken => "unexpected token",
+            ParseErrorKind::UndefinedLabel => "undefined label",
+            ParseErrorKind::DuplicateLabel => "duplicate label",
+            ParseErrorKind::InvalidRegister => "invalid register",
+            ParseErrorKind::UnknownDialect => "unknown dialect",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+// Line and column are 1-based, the column counts characters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)?;
+        if !self.token.is_empty() {
+            write!(f, " `{}`", self.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error(&self, line_index: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: line_index + 1,
+            column: self.column,
+            token: self.text.to_string(),
+            kind,
+        }
+    }
+}
+
+enum Body<'a> {
+    Empty,
+    Directive(Token<'a>, Vec<Token<'a>>),
+    Operation(Token<'a>, Vec<Token<'a>>),
+}
+
+struct Statement<'a> {
+    label: Option<Token<'a>>,
+    body: Body<'a>,
+}
+
+const V1_OPCODES: [&str; 3] = ["nop", "acc", "jmp"];
+const V2_OPCODES: [&str; 10] = [
+    "nop", "acc", "jmp", "set", "add", "mul", "jz", "jnz", "inp", "out",
+];
+
+fn is_opcode(s: &str, dialect: Dialect) -> bool {
+    match dialect {
+        Dialect::V1 => V1_OPCODES.contains(&s),
+        Dialect::V2 => V2_OPCODES.contains(&s),
+    }
+}
+
+fn arity(opcode: &str) -> usize {
+    match opcode {
+        "set" | "add" | "mul" | "jz" | "jnz" => 2,
+        _ => 1,
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    for (column, (index, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (true, Some((start_index, start_column))) => {
+                tokens.push(Token {
+                    text: &line[start_index..index],
+                    column: start_column + 1,
+                });
+                start = None;
+            }
+            (false, None) => start = Some((index, column)),
+            _ => (),
+        }
+    }
+    if let Some((start_index, start_column)) = start {
+        tokens.push(Token {
+            text: &line[start_index..],
+            column: start_column + 1,
+        });
+    }
+    tokens
+}
+
+fn is_label(s: &str) -> bool {
+    lazy_static! {
+        static ref LABEL_RE: regex::Regex = regex::Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    }
+    LABEL_RE.is_match(s)
+}
+
+fn is_number(s: &str) -> bool {
+    lazy_static! {
+        static ref NUMBER_RE: regex::Regex = regex::Regex::new(r"^[+-]?\d+$").unwrap();
+    }
+    NUMBER_RE.is_match(s)
+}
+
+fn parse_statement(line: &str, line_index: usize) -> Result<Statement<'_>, ParseError> {
+    let mut tokens = tokenize(strip_comment(line)).into_iter().peekable();
+
+    let label = match tokens.peek() {
+        Some(token) if token.text.ends_with(':') => {
+            let token = Token {
+                text: &token.text[..token.text.len() - 1],
+                column: token.column,
+            };
+            tokens.next();
+            if !is_label(token.text) {
+                return Err(token.error(line_index, ParseErrorKind::UnexpectedToken));
+            }
+            Some(token)
+        }
+        _ => None,
+    };
+
+    let body = match tokens.next() {
+        None => Body::Empty,
+        Some(token) if token.text.starts_with('.') => Body::Directive(token, tokens.collect()),
+        Some(token) => Body::Operation(token, tokens.collect()),
+    };
+    Ok(Statement { label, body })
+}
+
+// Resolves a jump operand, either a literal offset or a label, to an offset.
+type Resolve<'r> = dyn Fn(Token) -> Result<i64, ParseError> + 'r;
+
+fn build_instruction(
+    opcode: Token,
+    operands: &[Token],
+    line_index: usize,
+    resolve: &Resolve,
+) -> Result<Instruction, ParseError> {
+    let number = |token: Token| -> Result<i64, ParseError> {
+        if is_number(token.text) {
+            token
+                .text
+                .parse::<i64>()
+                .map_err(|_| token.error(line_index, ParseErrorKind::Overflow))
+        } else if is_label(token.text) {
+            Err(token.error(line_index, ParseErrorKind::UnexpectedToken))
+        } else {
+            Err(token.error(line_index, ParseErrorKind::MalformedNumber))
+        }
+    };
+    let offset = |token: Token| -> Result<isize, ParseError> {
+        let offset = if is_label(token.text) {
+            resolve(token)?
+        } else {
+            number(token)?
+        };
+        isize::try_from(offset).map_err(|_| token.error(line_index, ParseErrorKind::Overflow))
+    };
+    let register = |token: Token| -> Result<Register, ParseError> {
+        Register::parse(token.text)
+            .ok_or_else(|| token.error(line_index, ParseErrorKind::InvalidRegister))
+    };
+    let source = |token: Token| -> Result<Operand, ParseError> {
+        if is_number(token.text) {
+            number(token).map(Operand::Immediate)
+        } else if is_label(token.text) {
+            register(token).map(Operand::Register)
+        } else {
+            Err(token.error(line_index, ParseErrorKind::MalformedNumber))
+        }
+    };
+
+    let arity = arity(opcode.text);
+    if operands.len() < arity {
+        return Err(opcode.error(line_index, ParseErrorKind::MissingOperand));
+    }
+    if let Some(extra) = operands.get(arity) {
+        return Err(extra.error(line_index, ParseErrorKind::UnexpectedToken));
+    }
+
+    match opcode.text {
+        "nop" => offset(operands[0]).map(|arg| Instruction::Nop(arg as i64)),
+        "acc" => number(operands[0]).map(Instruction::Acc),
+        "jmp" => offset(operands[0]).map(Instruction::Jmp),
+        "set" => Ok(Instruction::Set(
+            register(operands[0])?,
+            source(operands[1])?,
+        )),
+        "add" => Ok(Instruction::Add(
+            register(operands[0])?,
+            source(operands[1])?,
+        )),
+        "mul" => Ok(Instruction::Mul(
+            register(operands[0])?,
+            source(operands[1])?,
+        )),
+        "jz" => Ok(Instruction::Jz(
+            register(operands[0])?,
+            offset(operands[1])?,
+        )),
+        "jnz" => Ok(Instruction::Jnz(
+            register(operands[0])?,
+            offset(operands[1])?,
+        )),
+        "inp" => register(operands[0]).map(Instruction::Inp),
+        "out" => source(operands[0]).map(Instruction::Out),
+        _ => Err(opcode.error(line_index, ParseErrorKind::UnknownOpcode)),
+    }
+}
+
+pub fn parse_instruction(s: &str, dialect: Dialect) -> Result<Instruction, ParseError> {
+    let statement = parse_statement(s, 0)?;
+    if let Some(label) = statement.label {
+        return Err(label.error(0, ParseErrorKind::UnexpectedToken));
+    }
+    match statement.body {
+        Body::Operation(opcode, _) if !is_opcode(opcode.text, dialect) => {
+            Err(opcode.error(0, ParseErrorKind::UnknownOpcode))
+        }
+        Body::Operation(opcode, operands) => build_instruction(opcode, &operands, 0, &|label| {
+            Err(label.error(0, ParseErrorKind::UndefinedLabel))
+        }),
+        Body::Directive(directive, _) => Err(directive.error(0, ParseErrorKind::UnexpectedToken)),
+        Body::Empty => Err(ParseError {
+            line: 1,
+            column: 1,
+            token: String::new(),
+            kind: ParseErrorKind::MissingOpcode,
+        }),
+    }
+}
+
+fn parse_directive(
+    directive: Token,
+    args: &[Token],
+    line_index: usize,
+) -> Result<Dialect, ParseError> {
+    if directive.text != ".dialect" {
+        return Err(directive.error(line_index, ParseErrorKind::UnexpectedToken));
+    }
+    match args {
+        [] => Err(directive.error(line_index, ParseErrorKind::MissingOperand)),
+        [version] => match version.text {
+            "1" => Ok(Dialect::V1),
+            "2" => Ok(Dialect::V2),
+            _ => Err(version.error(line_index, ParseErrorKind::UnknownDialect)),
+        },
+        [_, extra, ..] => Err(extra.error(line_index, ParseErrorKind::UnexpectedToken)),
+    }
+}
+
+pub fn assemble(input: &str, dialect: Dialect) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    let mut dialect = dialect;
+    let mut errors: Vec<ParseError> = Vec::new();
+    let mut operations: Vec<(usize, Token, Vec<Token>)> = Vec::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    for (line_index, line) in input.lines().enumerate() {
+        let statement = match parse_statement(line, line_index) {
+            Ok(statement) => statement,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+        if let Some(label) = statement.label {
+            if labels.insert(label.text, operations.len()).is_some() {
+                errors.push(label.error(line_index, ParseErrorKind::DuplicateLabel));
+            }
+        }
+        match statement.body {
+            Body::Empty => (),
+            Body::Directive(directive, _) if !operations.is_empty() => {
+                errors.push(directive.error(line_index, ParseErrorKind::UnexpectedToken));
+            }
+            Body::Directive(directive, args) => match parse_directive(directive, &args, line_index)
+            {
+                Ok(version) => dialect = version,
+                Err(error) => errors.push(error),
+            },
+            Body::Operation(opcode, _) if !is_opcode(opcode.text, dialect) => {
+                errors.push(opcode.error(line_index, ParseErrorKind::UnknownOpcode));
+            }
+            Body::Operation(opcode, operands) => operations.push((line_index, opcode, operands)),
+        }
+    }
+
+    let mut program: Vec<Instruction> = Vec::with_capacity(operations.len());
+    for (line_index, opcode, operands) in operations.iter() {
+        let pc = program.len();
+        let resolve = |label: Token| match labels.get(label.text) {
+            Some(target) => Ok(*target as i64 - pc as i64),
+            None => Err(label.error(*line_index, ParseErrorKind::UndefinedLabel)),
+        };
+        match build_instruction(*opcode, operands, *line_index, &resolve) {
+            Ok(instruction) => program.push(instruction),
+            Err(error) => {
+                errors.push(error);
+                program.push(Instruction::Nop(0));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        errors.sort_by_key(|error| (error.line, error.column));
+        Err(errors)
+    }
+}
+
+// The index of the source line each instruction comes from, for input that
+// assembles.
+pub fn instruction_lines(input: &str) -> Vec<usize> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(line_index, line)| {
+            matches!(
+                parse_statement(line, *line_index),
+                Ok(Statement {
+                    body: Body::Operation(_, _),
+                    ..
+                })
+            )
+        })
+        .map(|(line_index, _)| line_index)
+        .collect()
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Immediate(value) => write!(f, "{:+}", value),
+            Operand::Register(register) => write!(f, "{}", register),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Nop(arg) => write!(f, "nop {:+}", arg),
+            Instruction::Acc(arg) => write!(f, "acc {:+}", arg),
+            Instruction::Jmp(offset) => write!(f, "jmp {:+}", offset),
+            Instruction::Set(register, operand) => write!(f, "set {} {}", register, operand),
+            Instruction::Add(register, operand) => write!(f, "add {} {}", register, operand),
+            Instruction::Mul(register, operand) => write!(f, "mul {} {}", register, operand),
+            Instruction::Jz(register, offset) => write!(f, "jz {} {:+}", register, offset),
+            Instruction::Jnz(register, offset) => write!(f, "jnz {} {:+}", register, offset),
+            Instruction::Inp(register) => write!(f, "inp {}", register),
+            Instruction::Out(operand) => write!(f, "out {}", operand),
+        }
+    }
+}
+
+fn dialect_header(program: &[Instruction]) -> &'static str {
+    match program_dialect(program) {
+        Dialect::V1 => "",
+        Dialect::V2 => ".dialect 2\n",
+    }
+}
+
+pub fn disassemble(program: &[Instruction]) -> String {
+    let mut output = String::from(dialect_header(program));
+    for instruction in program.iter() {
+        output.push_str(&format!("{}\n", instruction));
+    }
+    output
+}
+
+pub fn disassemble_with_labels(program: &[Instruction]) -> String {
+    let jump_target = |pc: usize, instruction: &Instruction| {
+        let target = (pc as isize).checked_add(instruction.jump_offset()?)?;
+        if target >= 0 && target as usize <= program.len() {
+            Some(target as usize)
+        } else {
+            None
+        }
+    };
+
+    let mut targets: Vec<usize> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instruction)| jump_target(pc, instruction))
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label_of = |target: usize| format!("L{}", targets.binary_search(&target).unwrap());
+
+    let mut output = String::from(dialect_header(program));
+    for pc in 0..=program.len() {
+        if targets.binary_search(&pc).is_ok() {
+            output.push_str(&format!("{}:\n", label_of(pc)));
+        }
+        if let Some(instruction) = program.get(pc) {
+            let line = match (jump_target(pc, instruction), instruction) {
+                (Some(target), Instruction::Jmp(_)) => format!("jmp {}", label_of(target)),
+                (Some(target), Instruction::Jz(register, _)) => {
+                    format!("jz {} {}", register, label_of(target))
+                }
+                (Some(target), Instruction::Jnz(register, _)) => {
+                    format!("jnz {} {}", register, label_of(target))
+                }
+                _ => instruction.to_string(),
+            };
+            output.push_str(&format!("    {}\n", line));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod asm_tests {
+    use super::*;
+
+    fn assemble_v1(input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+        assemble(input, Dialect::V1)
+    }
+
+    fn parse_instruction_v1(s: &str) -> Result<Instruction, ParseError> {
+        parse_instruction(s, Dialect::V1)
+    }
+
+    const EXAMPLE1: &str = "nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6
+";
+
+    #[test]
+    fn test_labels() {
+        let source = "
+# the day 8 example, with labels
+        nop +0
+top:    acc +1
+        jmp skip
+back:   acc +3
+        jmp top     # loops forever
+        acc -99
+skip:
+        acc +1
+        jmp back
+        acc +6
+";
+        assert_eq!(assemble_v1(source).unwrap(), assemble_v1(EXAMPLE1).unwrap());
+    }
+
+    #[test]
+    fn test_label_at_exit() {
+        let program = assemble_v1("jmp end\nacc +1\nend:\n").unwrap();
+        assert_eq!(program, vec![Instruction::Jmp(2), Instruction::Acc(1)]);
+    }
+
+    fn error(line: usize, column: usize, token: &str, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line,
+            column,
+            token: token.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        use ParseErrorKind::*;
+        let single = |source: &str| assemble_v1(source).unwrap_err().remove(0);
+        assert_eq!(
+            single("nop +0\njmp nowhere\n"),
+            error(2, 5, "nowhere", UndefinedLabel)
+        );
+        assert_eq!(
+            single("a: nop +0\na: nop +0\n"),
+            error(2, 1, "a", DuplicateLabel)
+        );
+        assert_eq!(single("a: acc a\n"), error(1, 8, "a", UnexpectedToken));
+        assert_eq!(single("acc +1 +2\n"), error(1, 8, "+2", UnexpectedToken));
+        assert_eq!(single("  mul +2\n"), error(1, 3, "mul", UnknownOpcode));
+        assert_eq!(single("jmp\n"), error(1, 1, "jmp", MissingOperand));
+        assert_eq!(single("acc +1x\n"), error(1, 5, "+1x", MalformedNumber));
+        assert_eq!(
+            single("acc +99999999999999999999\n"),
+            error(1, 5, "+99999999999999999999", Overflow)
+        );
+        assert_eq!(single("1x: nop +0\n"), error(1, 1, "1x", UnexpectedToken));
+    }
+
+    #[test]
+    fn test_all_errors() {
+        let errors = assemble_v1("nop +0\nfoo +1\nacc\njmp +2\njmp bar\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert_eq!(errors[0].to_string(), "2:1: unknown opcode `foo`");
+    }
+
+    #[test]
+    fn test_parse_instruction() {
+        assert_eq!(parse_instruction_v1("acc -7"), Ok(Instruction::Acc(-7)));
+        assert_eq!(
+            parse_instruction_v1("jmp loop"),
+            Err(error(1, 5, "loop", ParseErrorKind::UndefinedLabel))
+        );
+        assert_eq!(
+            parse_instruction_v1(""),
+            Err(error(1, 1, "", ParseErrorKind::MissingOpcode))
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = assemble_v1(EXAMPLE1).unwrap();
+        assert_eq!(disassemble(&program), EXAMPLE1);
+        assert_eq!(assemble_v1(&disassemble(&program)), Ok(program));
+    }
+
+    #[test]
+    fn test_disassemble_with_labels() {
+        let program = assemble_v1(EXAMPLE1).unwrap();
+        let source = disassemble_with_labels(&program);
+        assert_eq!(
+            source,
+            "    nop +0
+L0:
+    acc +1
+    jmp L2
+L1:
+    acc +3
+    jmp L0
+    acc -99
+L2:
+    acc +1
+    jmp L1
+    acc +6
+"
+        );
+        assert_eq!(assemble_v1(&source), Ok(program));
+    }
+
+    #[test]
+    fn test_disassemble_out_of_range() {
+        let program = vec![
+            Instruction::Jmp(-1),
+            Instruction::Jmp(2),
+            Instruction::Jmp(3),
+            Instruction::Jmp(isize::MAX),
+        ];
+        assert_eq!(
+            disassemble_with_labels(&program),
+            "    jmp -1\n    jmp L0\n    jmp +3\nL0:\n    jmp +9223372036854775807\n"
+        );
+        assert_eq!(assemble_v1(&disassemble_with_labels(&program)), Ok(program));
+    }
+
+    const COUNTDOWN: &str = "
+.dialect 2
+        inp a           # count down from the input
+loop:   jz a done
+        add acc a
+        add a -1
+        jmp loop
+done:   out acc
+";
+
+    #[test]
+    fn test_dialect2() {
+        let program = assemble_v1(COUNTDOWN).unwrap();
+        assert_eq!(
+            program,
+            vec![
+                Instruction::Inp(Register::A),
+                Instruction::Jz(Register::A, 4),
+                Instruction::Add(Register::Acc, Operand::Register(Register::A)),
+                Instruction::Add(Register::A, Operand::Immediate(-1)),
+                Instruction::Jmp(-3),
+                Instruction::Out(Operand::Register(Register::Acc)),
+            ]
+        );
+        assert_eq!(assemble(COUNTDOWN, Dialect::V2), Ok(program.clone()));
+        assert_eq!(assemble_v1(&disassemble(&program)), Ok(program.clone()));
+        assert_eq!(
+            assemble_v1(&disassemble_with_labels(&program)),
+            Ok(program.clone())
+        );
+        assert!(disassemble(&program).starts_with(".dialect 2\ninp a\n"));
+    }
+
+    #[test]
+    fn test_dialect_errors() {
+        use ParseErrorKind::*;
+        let single = |source: &str| assemble_v1(source).unwrap_err().remove(0);
+        assert_eq!(single("set a +1\n"), error(1, 1, "set", UnknownOpcode));
+        assert_eq!(
+            single("nop +0\n.dialect 2\n"),
+            error(2, 1, ".dialect", UnexpectedToken)
+        );
+        assert_eq!(single(".dialect 3\n"), error(1, 10, "3", UnknownDialect));
+        assert_eq!(
+            single(".dialect 2\nset e +1\n"),
+            error(2, 5, "e", InvalidRegister)
+        );
+        assert_eq!(
+            single(".dialect 2\nadd a\n"),
+            error(2, 1, "add", MissingOperand)
+        );
+        assert_eq!(
+            parse_instruction("mul b c", Dialect::V2),
+            Ok(Instruction::Mul(
+                Register::B,
+                Operand::Register(Register::C)
+            ))
+        );
+        assert_eq!(
+            parse_instruction_v1("mul b c"),
+            Err(error(1, 1, "mul", UnknownOpcode))
+        );
+    }
+}
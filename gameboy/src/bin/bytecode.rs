@@ -0,0 +1,53 @@
+use std::env;
+use std::fs;
+use std::io::BufWriter;
+use std::process;
+
+const USAGE: &str = "Usage: bytecode (assemble|disassemble) input-filename output-filename";
+
+fn assemble(input: &str, output: &str) -> Result<(), String> {
+    let text = fs::read_to_string(input).map_err(|error| format!("{}: {}", input, error))?;
+    let program = gameboy::parse_program(&text).map_err(|errors| {
+        errors
+            .iter()
+            .map(|error| format!("{}:{}", input, error))
+            .collect::<Vec<String>>()
+            .join("\n")
+    })?;
+    let file = fs::File::create(output).map_err(|error| format!("{}: {}", output, error))?;
+    gameboy::write_bytecode(&program, BufWriter::new(file))
+        .map_err(|error| format!("{}: {}", output, error))?;
+    println!("Wrote {} instructions to {}", program.len(), output);
+    Ok(())
+}
+
+fn disassemble(input: &str, output: &str) -> Result<(), String> {
+    let file = fs::File::open(input).map_err(|error| format!("{}: {}", input, error))?;
+    let program = gameboy::load_bytecode(std::io::BufReader::new(file))
+        .map_err(|error| format!("{}: {}", input, error))?;
+    fs::write(output, gameboy::disassemble(&program))
+        .map_err(|error| format!("{}: {}", output, error))?;
+    println!("Wrote {} instructions to {}", program.len(), output);
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let result = match args[1].as_str() {
+        "assemble" => assemble(&args[2], &args[3]),
+        "disassemble" => disassemble(&args[2], &args[3]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
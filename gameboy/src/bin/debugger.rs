@@ -0,0 +1,36 @@
+use gameboy::debugger::Debugger;
+use gameboy::Machine;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let filename = args.get(1).expect("Usage: debugger input-filename");
+
+    let input = fs::read_to_string(filename).expect("Failed to read file");
+    let program = match gameboy::parse_program(&input) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                println!("error: {}:{}", filename, error);
+            }
+            return;
+        }
+    };
+    println!(
+        "Loaded {} instructions from {}, type help for commands",
+        program.len(),
+        filename
+    );
+
+    let stdin = io::stdin();
+    let mut debugger = Debugger::new(Machine::new(&program));
+    if stdin.is_terminal() {
+        debugger = debugger.with_prompt("(gameboy) ");
+    }
+    debugger
+        .run(stdin.lock(), io::stdout())
+        .expect("Failed to talk to the terminal");
+}
@@ -0,0 +1,401 @@
+use super::{Instruction, Operand, Register};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+// Layout, all integers little endian:
+//
+//   magic     4 bytes  "GBC\0"
+//   version   u16
+//   reserved  u16      zero
+//   count     u32      number of instructions
+//   length    u32      number of body bytes
+//   checksum  u32      32-bit FNV-1a of the body
+//   body      one opcode byte per instruction followed by its operands
+//
+// Numbers are zigzag encoded LEB128 varints, registers are one byte and
+// operands are a tag byte, 0 for an immediate and 1 for a register,
+// followed by the number or register.
+
+const MAGIC: [u8; 4] = *b"GBC\0";
+const VERSION: u16 = 1;
+const HEADER_LENGTH: usize = 20;
+
+const NOP: u8 = 0;
+const ACC: u8 = 1;
+const JMP: u8 = 2;
+const SET: u8 = 3;
+const ADD: u8 = 4;
+const MUL: u8 = 5;
+const JZ: u8 = 6;
+const JNZ: u8 = 7;
+const INP: u8 = 8;
+const OUT: u8 = 9;
+
+const IMMEDIATE: u8 = 0;
+const REGISTER: u8 = 1;
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u16),
+    ChecksumMismatch(u32, u32),
+    // Byte offset into the body for the errors below.
+    Truncated(usize),
+    UnknownOpcode(u8, usize),
+    InvalidRegister(u8, usize),
+    InvalidOperand(u8, usize),
+    Overflow(usize),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::Io(error) => write!(f, "{}", error),
+            BytecodeError::BadMagic(magic) => write!(f, "not bytecode, magic {:?}", magic),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bytecode version {}", version)
+            }
+            BytecodeError::ChecksumMismatch(expected, actual) => write!(
+                f,
+                "checksum mismatch, expected {:08x} but got {:08x}",
+                expected, actual
+            ),
+            BytecodeError::Truncated(offset) => write!(f, "{}: truncated body", offset),
+            BytecodeError::UnknownOpcode(opcode, offset) => {
+                write!(f, "{}: unknown opcode {}", offset, opcode)
+            }
+            BytecodeError::InvalidRegister(register, offset) => {
+                write!(f, "{}: invalid register {}", offset, register)
+            }
+            BytecodeError::InvalidOperand(tag, offset) => {
+                write!(f, "{}: invalid operand tag {}", offset, tag)
+            }
+            BytecodeError::Overflow(offset) => write!(f, "{}: number out of range", offset),
+            BytecodeError::TrailingBytes(offset) => {
+                write!(f, "{}: bytes after the last instruction", offset)
+            }
+        }
+    }
+}
+
+impl error::Error for BytecodeError {}
+
+impl From<io::Error> for BytecodeError {
+    fn from(error: io::Error) -> Self {
+        BytecodeError::Io(error)
+    }
+}
+
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn push_number(body: &mut Vec<u8>, number: i64) {
+    let mut zigzag = ((number << 1) ^ (number >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            body.push(byte);
+            return;
+        }
+        body.push(byte | 0x80);
+    }
+}
+
+fn push_register(body: &mut Vec<u8>, register: &Register) {
+    body.push(register.index() as u8);
+}
+
+fn push_operand(body: &mut Vec<u8>, operand: &Operand) {
+    match operand {
+        Operand::Immediate(number) => {
+            body.push(IMMEDIATE);
+            push_number(body, *number);
+        }
+        Operand::Register(register) => {
+            body.push(REGISTER);
+            push_register(body, register);
+        }
+    }
+}
+
+pub fn encode(program: &[Instruction]) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+    for instruction in program {
+        match instruction {
+            Instruction::Nop(arg) => {
+                body.push(NOP);
+                push_number(&mut body, *arg);
+            }
+            Instruction::Acc(arg) => {
+                body.push(ACC);
+                push_number(&mut body, *arg);
+            }
+            Instruction::Jmp(offset) => {
+                body.push(JMP);
+                push_number(&mut body, *offset as i64);
+            }
+            Instruction::Set(register, operand)
+            | Instruction::Add(register, operand)
+            | Instruction::Mul(register, operand) => {
+                body.push(match instruction {
+                    Instruction::Set(..) => SET,
+                    Instruction::Add(..) => ADD,
+                    _ => MUL,
+                });
+                push_register(&mut body, register);
+                push_operand(&mut body, operand);
+            }
+            Instruction::Jz(register, offset) | Instruction::Jnz(register, offset) => {
+                body.push(if let Instruction::Jz(..) = instruction {
+                    JZ
+                } else {
+                    JNZ
+                });
+                push_register(&mut body, register);
+                push_number(&mut body, *offset as i64);
+            }
+            Instruction::Inp(register) => {
+                body.push(INP);
+                push_register(&mut body, register);
+            }
+            Instruction::Out(operand) => {
+                body.push(OUT);
+                push_operand(&mut body, operand);
+            }
+        }
+    }
+    body
+}
+
+pub fn write_bytecode<W: Write>(program: &[Instruction], mut writer: W) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "program too large");
+    let body = encode(program);
+    let count = u32::try_from(program.len()).map_err(|_| too_large())?;
+    let length = u32::try_from(body.len()).map_err(|_| too_large())?;
+
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_LENGTH);
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&count.to_le_bytes());
+    header.extend_from_slice(&length.to_le_bytes());
+    header.extend_from_slice(&checksum(&body).to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(&body)?;
+    writer.flush()
+}
+
+struct Decoder<'a> {
+    body: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self
+            .body
+            .get(self.offset)
+            .ok_or(BytecodeError::Truncated(self.offset))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<i64, BytecodeError> {
+        let start = self.offset;
+        let mut zigzag: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift == 63 && byte > 1 {
+                return Err(BytecodeError::Overflow(start));
+            }
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+            }
+            shift += 7;
+        }
+    }
+
+    fn offset(&mut self) -> Result<isize, BytecodeError> {
+        let start = self.offset;
+        isize::try_from(self.number()?).map_err(|_| BytecodeError::Overflow(start))
+    }
+
+    fn register(&mut self) -> Result<Register, BytecodeError> {
+        let byte = self.byte()?;
+        Register::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or(BytecodeError::InvalidRegister(byte, self.offset - 1))
+    }
+
+    fn operand(&mut self) -> Result<Operand, BytecodeError> {
+        match self.byte()? {
+            IMMEDIATE => Ok(Operand::Immediate(self.number()?)),
+            REGISTER => Ok(Operand::Register(self.register()?)),
+            tag => Err(BytecodeError::InvalidOperand(tag, self.offset - 1)),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, BytecodeError> {
+        Ok(match self.byte()? {
+            NOP => Instruction::Nop(self.number()?),
+            ACC => Instruction::Acc(self.number()?),
+            JMP => Instruction::Jmp(self.offset()?),
+            SET => Instruction::Set(self.register()?, self.operand()?),
+            ADD => Instruction::Add(self.register()?, self.operand()?),
+            MUL => Instruction::Mul(self.register()?, self.operand()?),
+            JZ => Instruction::Jz(self.register()?, self.offset()?),
+            JNZ => Instruction::Jnz(self.register()?, self.offset()?),
+            INP => Instruction::Inp(self.register()?),
+            OUT => Instruction::Out(self.operand()?),
+            opcode => return Err(BytecodeError::UnknownOpcode(opcode, self.offset - 1)),
+        })
+    }
+}
+
+pub fn decode(body: &[u8], count: usize) -> Result<Vec<Instruction>, BytecodeError> {
+    let mut decoder = Decoder { body, offset: 0 };
+    // Every instruction takes at least two bytes, which keeps a corrupt count
+    // from reserving more than the body could hold.
+    let mut program: Vec<Instruction> = Vec::with_capacity(count.min(body.len() / 2));
+    for _ in 0..count {
+        program.push(decoder.instruction()?);
+    }
+    if decoder.offset != body.len() {
+        return Err(BytecodeError::TrailingBytes(decoder.offset));
+    }
+    Ok(program)
+}
+
+pub fn load_bytecode<R: Read>(mut reader: R) -> Result<Vec<Instruction>, BytecodeError> {
+    let mut header = [0u8; HEADER_LENGTH];
+    reader.read_exact(&mut header)?;
+    let field = |at: usize| {
+        u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+
+    let magic = [header[0], header[1], header[2], header[3]];
+    if magic != MAGIC {
+        return Err(BytecodeError::BadMagic(magic));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let count = field(8) as usize;
+    let length = field(12) as usize;
+    let expected = field(16);
+
+    let mut body: Vec<u8> = Vec::new();
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(BytecodeError::Truncated(body.len()));
+    }
+    let actual = checksum(&body);
+    if actual != expected {
+        return Err(BytecodeError::ChecksumMismatch(expected, actual));
+    }
+    decode(&body, count)
+}
+
+#[cfg(test)]
+mod bytecode_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+
+    fn write(program: &[Instruction]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        write_bytecode(program, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = write(&EXAMPLE1);
+        assert_eq!(bytes.len(), HEADER_LENGTH + 9 * 2 + 1);
+        assert_eq!(load_bytecode(&bytes[..]).unwrap(), EXAMPLE1.to_vec());
+
+        let program = crate::parse_program(
+            ".dialect 2\nset a +3\nloop: add acc a\nmul b acc\nadd a -1\njnz a loop\njz b -5\ninp c\nout d\nout -9223372036854775808\nnop +9223372036854775807\n",
+        )
+        .unwrap();
+        assert_eq!(load_bytecode(&write(&program)[..]).unwrap(), program);
+        assert_eq!(load_bytecode(&write(&[])[..]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_header_errors() {
+        let mut bytes = write(&EXAMPLE1);
+        bytes[0] = b'X';
+        assert!(matches!(
+            load_bytecode(&bytes[..]),
+            Err(BytecodeError::BadMagic(_))
+        ));
+
+        let mut bytes = write(&EXAMPLE1);
+        bytes[4] = 2;
+        assert!(matches!(
+            load_bytecode(&bytes[..]),
+            Err(BytecodeError::UnsupportedVersion(2))
+        ));
+
+        let bytes = write(&EXAMPLE1);
+        assert!(matches!(
+            load_bytecode(&bytes[..10]),
+            Err(BytecodeError::Io(_))
+        ));
+        assert!(matches!(
+            load_bytecode(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::Truncated(18))
+        ));
+    }
+
+    #[test]
+    fn test_body_errors() {
+        let mut bytes = write(&EXAMPLE1);
+        bytes[HEADER_LENGTH + 2] = 42;
+        assert!(matches!(
+            load_bytecode(&bytes[..]),
+            Err(BytecodeError::ChecksumMismatch(_, _))
+        ));
+
+        assert!(matches!(
+            decode(&[42, 0], 1),
+            Err(BytecodeError::UnknownOpcode(42, 0))
+        ));
+        assert!(matches!(
+            decode(&[INP, 7], 1),
+            Err(BytecodeError::InvalidRegister(7, 1))
+        ));
+        assert!(matches!(
+            decode(&[OUT, 2, 0], 1),
+            Err(BytecodeError::InvalidOperand(2, 1))
+        ));
+        assert!(matches!(
+            decode(
+                &[ACC, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+                1
+            ),
+            Err(BytecodeError::Overflow(1))
+        ));
+        assert!(matches!(
+            decode(&[NOP, 0, NOP, 0], 1),
+            Err(BytecodeError::TrailingBytes(2))
+        ));
+        assert!(matches!(
+            decode(&[NOP, 0], 2),
+            Err(BytecodeError::Truncated(2))
+        ));
+    }
+}
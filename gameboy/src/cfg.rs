@@ -0,0 +1,389 @@
+use super::Instruction;
+use std::fmt::Write;
+
+// The exit is the node just past the last instruction, pc == program.len().
+// Jumps outside of 0..=len are faults and have no node to go to.
+//
+// Conditional jumps have two successors and the analysis does not track
+// register values, so for such programs the termination is only decided
+// when every path agrees.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Termination {
+    Terminates,
+    Loops,
+    Faults,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub faults: bool,
+}
+
+pub struct ControlFlowGraph {
+    successors: Vec<Vec<usize>>,
+    faults: Vec<bool>,
+    blocks: Vec<BasicBlock>,
+}
+
+// A target that does not fit in an isize is out of bounds, and reported as
+// -1 so that it becomes a fault.
+fn targets(pc: usize, instruction: &Instruction) -> Vec<isize> {
+    let target = |offset: isize| (pc as isize).checked_add(offset).unwrap_or(-1);
+    match instruction {
+        Instruction::Jmp(offset) => vec![target(*offset)],
+        Instruction::Jz(_, offset) | Instruction::Jnz(_, offset) => {
+            vec![target(1), target(*offset)]
+        }
+        _ => vec![target(1)],
+    }
+}
+
+fn is_branch(instruction: &Instruction) -> bool {
+    instruction.jump_offset().is_some()
+}
+
+impl ControlFlowGraph {
+    pub fn new(program: &[Instruction]) -> Self {
+        let exit = program.len();
+        let mut successors: Vec<Vec<usize>> = Vec::with_capacity(exit);
+        let mut faults: Vec<bool> = Vec::with_capacity(exit);
+        let mut leaders: Vec<bool> = vec![false; exit + 1];
+        leaders[0] = true;
+        for (pc, instruction) in program.iter().enumerate() {
+            let (inside, outside): (Vec<isize>, Vec<isize>) = targets(pc, instruction)
+                .into_iter()
+                .partition(|target| *target >= 0 && *target as usize <= exit);
+            let inside: Vec<usize> = inside.into_iter().map(|target| target as usize).collect();
+            if is_branch(instruction) {
+                leaders[pc + 1] = true;
+                for target in inside.iter() {
+                    leaders[*target] = true;
+                }
+            }
+            successors.push(inside);
+            faults.push(!outside.is_empty());
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut start: usize = 0;
+        for pc in 0..exit {
+            if leaders[pc + 1] || pc + 1 == exit {
+                blocks.push(BasicBlock {
+                    start,
+                    end: pc + 1,
+                    successors: successors[pc].clone(),
+                    faults: faults[pc],
+                });
+                start = pc + 1;
+            }
+        }
+
+        Self {
+            successors,
+            faults,
+            blocks,
+        }
+    }
+
+    pub fn exit(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn successors(&self, pc: usize) -> &[usize] {
+        &self.successors[pc]
+    }
+
+    pub fn faults(&self, pc: usize) -> bool {
+        self.faults[pc]
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block_of(&self, pc: usize) -> Option<&BasicBlock> {
+        let index = self.blocks.partition_point(|block| block.end <= pc);
+        self.blocks.get(index)
+    }
+
+    // Indexed by pc, with the exit as the last entry.
+    pub fn reachable(&self) -> Vec<bool> {
+        let exit = self.exit();
+        let mut reachable: Vec<bool> = vec![false; exit + 1];
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(pc) = stack.pop() {
+            if reachable[pc] {
+                continue;
+            }
+            reachable[pc] = true;
+            if pc < exit {
+                stack.extend(self.successors[pc].iter());
+            }
+        }
+        reachable
+    }
+
+    pub fn unreachable(&self) -> Vec<usize> {
+        let reachable = self.reachable();
+        (0..self.exit()).filter(|pc| !reachable[*pc]).collect()
+    }
+
+    // Tarjan's algorithm, without recursion so that long programs do not
+    // overflow the stack. Components come out in reverse topological order.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let count = self.exit();
+        let mut index: Vec<Option<usize>> = vec![None; count];
+        let mut low_link: Vec<usize> = vec![0; count];
+        let mut on_stack: Vec<bool> = vec![false; count];
+        let mut stack: Vec<usize> = Vec::new();
+        let mut components: Vec<Vec<usize>> = Vec::new();
+        let mut next_index: usize = 0;
+
+        for root in 0..count {
+            if index[root].is_some() {
+                continue;
+            }
+            let mut work: Vec<(usize, usize)> = vec![(root, 0)];
+            while let Some((pc, child)) = work.pop() {
+                if child == 0 {
+                    index[pc] = Some(next_index);
+                    low_link[pc] = next_index;
+                    next_index += 1;
+                    stack.push(pc);
+                    on_stack[pc] = true;
+                } else {
+                    let previous = self.successors[pc][child - 1];
+                    low_link[pc] = low_link[pc].min(low_link[previous]);
+                }
+
+                let mut descended = false;
+                for (position, successor) in self.successors[pc].iter().enumerate().skip(child) {
+                    if *successor == count {
+                        continue;
+                    }
+                    match index[*successor] {
+                        None => {
+                            work.push((pc, position + 1));
+                            work.push((*successor, 0));
+                            descended = true;
+                            break;
+                        }
+                        Some(successor_index) if on_stack[*successor] => {
+                            low_link[pc] = low_link[pc].min(successor_index);
+                        }
+                        _ => (),
+                    }
+                }
+                if descended {
+                    continue;
+                }
+
+                if Some(low_link[pc]) == index[pc] {
+                    let mut component: Vec<usize> = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == pc {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+
+    pub fn loops(&self) -> Vec<Vec<usize>> {
+        self.strongly_connected_components()
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.successors[component[0]].contains(&component[0])
+            })
+            .collect()
+    }
+
+    // Only control flow is modelled: a program that terminates here can
+    // still fail at run time with an arithmetic overflow, exhausted input,
+    // or an exceeded accumulator, step or time limit.
+    pub fn termination(&self) -> Termination {
+        let reachable = self.reachable();
+        let exit = self.exit();
+        let exits = reachable[exit];
+        let faults = (0..exit).any(|pc| reachable[pc] && self.faults[pc]);
+        let loops = self.loops().iter().any(|component| reachable[component[0]]);
+        match (exits, faults, loops) {
+            (true, false, false) => Termination::Terminates,
+            (false, false, _) => Termination::Loops,
+            (false, true, false) => Termination::Faults,
+            _ => Termination::Unknown,
+        }
+    }
+
+    pub fn to_dot(&self, program: &[Instruction]) -> String {
+        let reachable = self.reachable();
+        let mut dot =
+            String::from("digraph program {\n    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for (offset, instruction) in program[block.start..block.end].iter().enumerate() {
+                write!(label, "{}: {}\\l", block.start + offset, instruction).unwrap();
+            }
+            let style = if reachable[block.start] {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+        writeln!(dot, "    exit [shape=doublecircle];").unwrap();
+        if self.blocks.iter().any(|block| block.faults) {
+            writeln!(dot, "    fault [shape=octagon];").unwrap();
+        }
+        for block in self.blocks.iter() {
+            for successor in block.successors.iter() {
+                if *successor == self.exit() {
+                    writeln!(dot, "    b{} -> exit;", block.start).unwrap();
+                } else {
+                    writeln!(dot, "    b{} -> b{};", block.start, successor).unwrap();
+                }
+            }
+            if block.faults {
+                writeln!(dot, "    b{} -> fault;", block.start).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+pub fn terminates(program: &[Instruction]) -> bool {
+    ControlFlowGraph::new(program).termination() == Termination::Terminates
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+
+    #[test]
+    fn test_jump_overflow() {
+        let program = [Instruction::Nop(0), Instruction::Jmp(isize::MAX)];
+        let cfg = ControlFlowGraph::new(&program);
+        assert!(cfg.faults(1));
+        assert!(cfg.successors(1).is_empty());
+        assert_eq!(cfg.termination(), Termination::Faults);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let cfg = ControlFlowGraph::new(&EXAMPLE1);
+        let ranges: Vec<(usize, usize, Vec<usize>)> = cfg
+            .blocks()
+            .iter()
+            .map(|block| (block.start, block.end, block.successors.clone()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 1, vec![1]),
+                (1, 3, vec![6]),
+                (3, 5, vec![1]),
+                (5, 6, vec![6]),
+                (6, 8, vec![3]),
+                (8, 9, vec![9]),
+            ]
+        );
+        assert_eq!(cfg.block_of(4).unwrap().start, 3);
+        assert!(cfg.block_of(9).is_none());
+    }
+
+    #[test]
+    fn test_reachable() {
+        let cfg = ControlFlowGraph::new(&EXAMPLE1);
+        assert_eq!(cfg.unreachable(), vec![5, 8]);
+        assert!(!cfg.reachable()[9]);
+    }
+
+    #[test]
+    fn test_loops() {
+        let cfg = ControlFlowGraph::new(&EXAMPLE1);
+        assert_eq!(cfg.loops(), vec![vec![1, 2, 3, 4, 6, 7]]);
+        assert_eq!(cfg.strongly_connected_components().len(), 4);
+        assert_eq!(cfg.termination(), Termination::Loops);
+
+        let self_loop = ControlFlowGraph::new(&[Instruction::Acc(1), Instruction::Jmp(0)]);
+        assert_eq!(self_loop.loops(), vec![vec![1]]);
+    }
+
+    #[test]
+    fn test_termination() {
+        let mut program = EXAMPLE1.to_vec();
+        program[7] = Instruction::Nop(-4);
+        assert!(terminates(&program));
+        assert!(!terminates(&EXAMPLE1));
+
+        program[8] = Instruction::Jmp(2);
+        assert_eq!(
+            ControlFlowGraph::new(&program).termination(),
+            Termination::Faults
+        );
+        assert!(terminates(&[]));
+    }
+
+    #[test]
+    fn test_long_chain() {
+        let mut program = vec![Instruction::Acc(1); 100_000];
+        program.push(Instruction::Jmp(-100_000));
+        let cfg = ControlFlowGraph::new(&program);
+        assert_eq!(cfg.loops().len(), 1);
+        assert_eq!(cfg.loops()[0].len(), 100_001);
+    }
+
+    #[test]
+    fn test_dot() {
+        let program = [
+            Instruction::Acc(1),
+            Instruction::Jmp(2),
+            Instruction::Jmp(-7),
+        ];
+        assert_eq!(
+            ControlFlowGraph::new(&program).to_dot(&program),
+            r#"digraph program {
+    node [shape=box, fontname=monospace];
+    b0 [label="0: acc +1\l1: jmp +2\l"];
+    b2 [label="2: jmp -7\l", style=dashed];
+    exit [shape=doublecircle];
+    fault [shape=octagon];
+    b0 -> exit;
+    b2 -> fault;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_conditional() {
+        let program = crate::parse_program(
+            ".dialect 2\ninp a\nloop: jz a done\nadd a -1\njmp loop\ndone: out a\n",
+        )
+        .unwrap();
+        let cfg = ControlFlowGraph::new(&program);
+        assert_eq!(cfg.successors(1), &[2, 4]);
+        assert_eq!(cfg.loops(), vec![vec![1, 2, 3]]);
+        assert_eq!(cfg.termination(), Termination::Unknown);
+
+        let mut program = program;
+        program[1] = Instruction::Jz(crate::Register::A, 1);
+        assert_eq!(
+            ControlFlowGraph::new(&program).termination(),
+            Termination::Loops
+        );
+    }
+}
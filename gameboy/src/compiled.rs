@@ -0,0 +1,215 @@
+use super::{ExitError, Instruction};
+use std::error;
+use std::fmt;
+
+// A lowered form of version 1 programs for workloads that run the same
+// program many times, like trying every mutation. Each instruction becomes
+// the amount it adds to the accumulator and the absolute pc it continues at,
+// so running it is a table lookup per step. Jumps outside of the program are
+// marked up front and only cost anything when they are taken.
+
+const FAULT: usize = usize::MAX;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileError {
+    // Only nop, acc and jmp can be compiled.
+    Unsupported(usize, Instruction),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Unsupported(pc, instruction) => {
+                write!(f, "{}: cannot compile {}", pc, instruction)
+            }
+        }
+    }
+}
+
+impl error::Error for CompileError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Op {
+    delta: i64,
+    next: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledProgram {
+    ops: Vec<Op>,
+    // Where each instruction continues, including targets outside of the
+    // program, for reporting faults.
+    targets: Vec<isize>,
+}
+
+impl CompiledProgram {
+    pub fn compile(program: &[Instruction]) -> Result<Self, CompileError> {
+        let mut compiled = CompiledProgram {
+            ops: vec![Op { delta: 0, next: 0 }; program.len()],
+            targets: vec![0; program.len()],
+        };
+        for (pc, instruction) in program.iter().enumerate() {
+            compiled.patch(pc, instruction)?;
+        }
+        Ok(compiled)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    // Replaces the instruction at pc, for instance to try a mutation and put
+    // the original back afterwards.
+    pub fn patch(&mut self, pc: usize, instruction: &Instruction) -> Result<(), CompileError> {
+        let (delta, offset) = match instruction {
+            Instruction::Nop(_) => (0, 1),
+            Instruction::Acc(arg) => (*arg, 1),
+            Instruction::Jmp(offset) => (0, *offset),
+            _ => return Err(CompileError::Unsupported(pc, instruction.clone())),
+        };
+        // A target that does not fit in an isize faults, and is reported
+        // saturated like execute_program does.
+        let next = match (pc as isize).checked_add(offset) {
+            Some(target) if target >= 0 && target as usize <= self.ops.len() => target as usize,
+            _ => FAULT,
+        };
+        self.ops[pc] = Op { delta, next };
+        self.targets[pc] = (pc as isize).saturating_add(offset);
+        Ok(())
+    }
+}
+
+// Runs compiled programs with the same results as execute_program. The
+// visited set is a bitset that is kept between runs and cleared word by word,
+// so running again allocates nothing.
+#[derive(Default)]
+pub struct Executor {
+    visited: Vec<u64>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn run(&mut self, program: &CompiledProgram) -> Result<i64, ExitError> {
+        let words = program.len().div_ceil(64);
+        self.visited.clear();
+        self.visited.resize(words, 0);
+
+        let exit = program.len();
+        let mut pc: usize = 0;
+        let mut accumulator: i64 = 0;
+        while pc != exit {
+            let (word, bit) = (pc / 64, 1u64 << (pc % 64));
+            if self.visited[word] & bit != 0 {
+                return Err(ExitError::InfiniteLoop(accumulator, pc));
+            }
+            self.visited[word] |= bit;
+
+            let op = program.ops[pc];
+            if op.next == FAULT {
+                return Err(ExitError::JumpOutOfBounds(pc, program.targets[pc]));
+            }
+            accumulator = accumulator
+                .checked_add(op.delta)
+                .ok_or(ExitError::ArithmeticOverflow(pc))?;
+            pc = op.next;
+        }
+        Ok(accumulator)
+    }
+}
+
+#[cfg(test)]
+mod compiled_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+    use crate::{execute_program, find_repairs, Mutation, Register};
+
+    #[test]
+    fn test_run() {
+        let mut compiled = CompiledProgram::compile(&EXAMPLE1).unwrap();
+        let mut executor = Executor::new();
+        assert_eq!(executor.run(&compiled), Err(ExitError::InfiniteLoop(5, 1)));
+
+        compiled.patch(7, &Instruction::Nop(-4)).unwrap();
+        assert_eq!(executor.run(&compiled), Ok(8));
+        compiled.patch(8, &Instruction::Jmp(2)).unwrap();
+        assert_eq!(
+            executor.run(&compiled),
+            Err(ExitError::JumpOutOfBounds(8, 10))
+        );
+        compiled.patch(8, &Instruction::Jmp(isize::MAX)).unwrap();
+        assert_eq!(
+            executor.run(&compiled),
+            Err(ExitError::JumpOutOfBounds(8, isize::MAX))
+        );
+        compiled.patch(0, &Instruction::Acc(i64::MAX)).unwrap();
+        assert_eq!(
+            executor.run(&compiled),
+            Err(ExitError::ArithmeticOverflow(1))
+        );
+
+        let empty = CompiledProgram::compile(&[]).unwrap();
+        assert_eq!(executor.run(&empty), Ok(0));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let program = [Instruction::Nop(0), Instruction::Inp(Register::A)];
+        assert_eq!(
+            CompiledProgram::compile(&program).unwrap_err(),
+            CompileError::Unsupported(1, Instruction::Inp(Register::A))
+        );
+    }
+
+    #[test]
+    fn test_matches_execute_program() {
+        let program = crate::parse_program(
+            "nop +2\nacc +5\njmp +3\nacc -2\njmp -4\njmp -2\nacc +7\njmp -6\nacc +1\njmp +70\n",
+        )
+        .unwrap();
+        let mut compiled = CompiledProgram::compile(&program).unwrap();
+        let mut executor = Executor::new();
+        let mut repairs: Vec<(usize, i64)> = Vec::new();
+        for (pc, instruction) in program.iter().enumerate() {
+            let swapped = match instruction {
+                Instruction::Nop(arg) => Instruction::Jmp(*arg as isize),
+                Instruction::Jmp(offset) => Instruction::Nop(*offset as i64),
+                _ => continue,
+            };
+            let mut mutated = program.clone();
+            mutated[pc] = swapped.clone();
+            compiled.patch(pc, &swapped).unwrap();
+            let result = executor.run(&compiled);
+            assert_eq!(result, execute_program(&mutated));
+            if let Ok(accumulator) = result {
+                repairs.push((pc, accumulator));
+            }
+            compiled.patch(pc, instruction).unwrap();
+        }
+        let expected: Vec<(usize, i64)> = find_repairs(&program, &[Mutation::SwapNopJmp])
+            .iter()
+            .map(|repair| (repair.pc, repair.accumulator))
+            .collect();
+        assert_eq!(repairs, expected);
+    }
+
+    #[test]
+    fn test_long_program() {
+        let mut program = vec![Instruction::Acc(1); 10_000];
+        program.push(Instruction::Jmp(-10_000));
+        let compiled = CompiledProgram::compile(&program).unwrap();
+        let mut executor = Executor::new();
+        for _ in 0..10 {
+            assert_eq!(
+                executor.run(&compiled),
+                Err(ExitError::InfiniteLoop(10_000, 0))
+            );
+        }
+    }
+}
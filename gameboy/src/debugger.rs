@@ -0,0 +1,438 @@
+use super::{Dialect, ExitError, Instruction, Machine, Register, Status};
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{BufRead, Write};
+
+// A line based debugger. Commands are read from any BufRead so that sessions
+// can be scripted, and every command answers with at least one line.
+
+const HELP: &str = "\
+break <pc>          stop before executing the instruction at pc
+delete <pc>         remove the breakpoint at pc
+watch [value]       stop when acc changes, or when it becomes value
+unwatch             remove all watchpoints
+step [count]        execute count instructions, 1 by default
+continue            execute until a breakpoint, watchpoint or exit
+back [count]        undo count instructions, 1 by default
+rewind <step>       undo until step instructions have been executed
+lastchange          show where acc was last changed
+backtrace [count]   show the last executed pcs, most recent first
+patch <pc> <instr>  replace the instruction at pc
+list [pc]           show the instructions around pc
+info                show pc, registers and breakpoints
+quit                leave the debugger";
+
+// The number of pcs shown by backtrace without a count.
+const DEFAULT_HISTORY_LENGTH: usize = 64;
+const LIST_CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watchpoint {
+    Change,
+    Equals(i64),
+}
+
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint(i64, i64),
+    Terminated,
+    Failed(ExitError),
+    Stepped,
+}
+
+pub struct Debugger<'a> {
+    machine: Machine<'a>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    history_length: usize,
+    prompt: Option<String>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(machine: Machine<'a>) -> Self {
+        Self {
+            machine: machine.with_undo_log(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            history_length: DEFAULT_HISTORY_LENGTH,
+            prompt: None,
+        }
+    }
+
+    pub fn with_history_length(mut self, history_length: usize) -> Self {
+        self.history_length = history_length;
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        self.prompt = Some(prompt.to_string());
+        self
+    }
+
+    pub fn machine(&self) -> &Machine<'a> {
+        &self.machine
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if let Some(prompt) = &self.prompt {
+                write!(output, "{}", prompt)?;
+                output.flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            if !self.execute(&line, &mut output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Runs a single command, returns false when the session should end.
+    pub fn execute<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let line = line.trim();
+        let (command, rest) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+        let number = |default: usize| -> Option<usize> {
+            if rest.is_empty() {
+                Some(default)
+            } else {
+                rest.parse().ok()
+            }
+        };
+
+        match command {
+            "" => (),
+            "break" | "b" => match rest.parse::<usize>() {
+                Ok(pc) if pc < self.machine.program().len() => {
+                    self.breakpoints.insert(pc);
+                    writeln!(output, "breakpoint at {}", pc)?;
+                }
+                _ => writeln!(output, "error: expected a pc inside the program")?,
+            },
+            "delete" | "d" => match rest.parse::<usize>() {
+                Ok(pc) if self.breakpoints.remove(&pc) => {
+                    writeln!(output, "deleted breakpoint at {}", pc)?
+                }
+                _ => writeln!(output, "error: no breakpoint at {}", rest)?,
+            },
+            "watch" | "w" => {
+                let watchpoint = if rest.is_empty() {
+                    Some(Watchpoint::Change)
+                } else {
+                    rest.parse().ok().map(Watchpoint::Equals)
+                };
+                match watchpoint {
+                    Some(Watchpoint::Change) => writeln!(output, "watching acc")?,
+                    Some(Watchpoint::Equals(value)) => {
+                        writeln!(output, "watching acc == {}", value)?
+                    }
+                    None => writeln!(output, "error: expected a value")?,
+                }
+                self.watchpoints.extend(watchpoint);
+            }
+            "unwatch" => {
+                self.watchpoints.clear();
+                writeln!(output, "removed all watchpoints")?;
+            }
+            "step" | "s" => match number(1) {
+                Some(count) => {
+                    let stop = self.advance(Some(count));
+                    self.report(stop, output)?;
+                }
+                None => writeln!(output, "error: expected a count")?,
+            },
+            "continue" | "c" => {
+                let stop = self.advance(None);
+                self.report(stop, output)?;
+            }
+            "back" => match number(1) {
+                Some(count) => {
+                    let target = self.machine.executed().saturating_sub(count);
+                    self.rewind(target, output)?;
+                }
+                None => writeln!(output, "error: expected a count")?,
+            },
+            "rewind" => match rest.parse::<usize>() {
+                Ok(step) if step <= self.machine.executed() => self.rewind(step, output)?,
+                _ => writeln!(
+                    output,
+                    "error: expected a step up to {}",
+                    self.machine.executed()
+                )?,
+            },
+            "lastchange" => match self.machine.last_accumulator_change() {
+                Some(change) => writeln!(
+                    output,
+                    "step {}, {:5}: acc {} -> {}",
+                    change.step, change.pc, change.old, change.new
+                )?,
+                None => writeln!(output, "acc never changed")?,
+            },
+            "backtrace" | "bt" => match number(self.history_length) {
+                Some(count) => {
+                    if self.machine.executed() == 0 {
+                        writeln!(output, "nothing executed")?;
+                    }
+                    for pc in self.machine.history().rev().take(count) {
+                        writeln!(output, "{:5}: {}", pc, self.machine.program()[pc])?;
+                    }
+                }
+                None => writeln!(output, "error: expected a count")?,
+            },
+            "patch" | "p" => self.patch(rest, output)?,
+            "list" | "l" => match number(self.machine.pc()) {
+                Some(pc) => self.list(pc, output)?,
+                None => writeln!(output, "error: expected a pc")?,
+            },
+            "info" | "i" => self.info(output)?,
+            "help" | "h" => writeln!(output, "{}", HELP)?,
+            "quit" | "q" => return Ok(false),
+            _ => writeln!(output, "error: unknown command {}, try help", command)?,
+        }
+        Ok(true)
+    }
+
+    // Steps at least once, so that continuing from a breakpoint moves on.
+    fn advance(&mut self, count: Option<usize>) -> Stop {
+        let mut remaining = count;
+        loop {
+            if self.machine.status() == Status::Terminated {
+                return Stop::Terminated;
+            }
+            if remaining == Some(0) {
+                return Stop::Stepped;
+            }
+            let old = self.machine.accumulator();
+            match self.machine.step() {
+                Ok(Status::Terminated) => return Stop::Terminated,
+                Ok(Status::Running) => (),
+                Err(error) => return Stop::Failed(error),
+            }
+            remaining = remaining.map(|remaining| remaining - 1);
+
+            let new = self.machine.accumulator();
+            let watched = self.watchpoints.iter().any(|watchpoint| match watchpoint {
+                Watchpoint::Change => new != old,
+                Watchpoint::Equals(value) => new != old && new == *value,
+            });
+            if watched {
+                return Stop::Watchpoint(old, new);
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Stop::Breakpoint(self.machine.pc());
+            }
+        }
+    }
+
+    fn rewind<W: Write>(&mut self, step: usize, output: &mut W) -> io::Result<()> {
+        self.machine.rewind_to(step);
+        writeln!(output, "at step {}", self.machine.executed())?;
+        self.where_am_i(output)
+    }
+
+    fn report<W: Write>(&self, stop: Stop, output: &mut W) -> io::Result<()> {
+        match stop {
+            Stop::Breakpoint(pc) => writeln!(output, "breakpoint at {}", pc)?,
+            Stop::Watchpoint(old, new) => writeln!(output, "watchpoint: acc {} -> {}", old, new)?,
+            Stop::Terminated => {
+                return writeln!(output, "terminated, acc {}", self.machine.accumulator())
+            }
+            Stop::Failed(error) => writeln!(output, "error: {}", error)?,
+            Stop::Stepped => (),
+        }
+        self.where_am_i(output)
+    }
+
+    fn where_am_i<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match self.machine.current() {
+            Some(instruction) => writeln!(
+                output,
+                "{:5}: {:12} acc {}",
+                self.machine.pc(),
+                instruction.to_string(),
+                self.machine.accumulator()
+            ),
+            None => writeln!(
+                output,
+                "{:5}: end of program, acc {}",
+                self.machine.pc(),
+                self.machine.accumulator()
+            ),
+        }
+    }
+
+    fn patch<W: Write>(&mut self, rest: &str, output: &mut W) -> io::Result<()> {
+        let (pc, text) = match rest.find(char::is_whitespace) {
+            Some(index) => (&rest[..index], rest[index..].trim()),
+            None => (rest, ""),
+        };
+        let pc = match pc.parse::<usize>() {
+            Ok(pc) if pc < self.machine.program().len() => pc,
+            _ => return writeln!(output, "error: expected a pc inside the program"),
+        };
+        match Instruction::parse_with_dialect(text, Dialect::V2) {
+            Ok(instruction) => {
+                let new = instruction.to_string();
+                if let Some(old) = self.machine.patch(pc, instruction) {
+                    writeln!(output, "{:5}: {} -> {}", pc, old, new)?;
+                }
+                Ok(())
+            }
+            Err(error) => writeln!(output, "error: {}", error),
+        }
+    }
+
+    fn list<W: Write>(&self, pc: usize, output: &mut W) -> io::Result<()> {
+        let program = self.machine.program();
+        if pc > program.len() {
+            return writeln!(output, "error: expected a pc inside the program");
+        }
+        let start = pc.saturating_sub(LIST_CONTEXT);
+        let end = pc.saturating_add(LIST_CONTEXT + 1).min(program.len());
+        for (index, instruction) in program.iter().enumerate().take(end).skip(start) {
+            let marker = if index == self.machine.pc() { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(&index) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(
+                output,
+                "{}{}{:4}: {}",
+                marker, breakpoint, index, instruction
+            )?;
+        }
+        if start >= end {
+            writeln!(output, "nothing to list at {}", pc)?;
+        }
+        Ok(())
+    }
+
+    fn info<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        writeln!(
+            output,
+            "pc {}, executed {}",
+            self.machine.pc(),
+            self.machine.executed()
+        )?;
+        let registers: Vec<String> = Register::ALL
+            .iter()
+            .map(|register| format!("{} {}", register, self.machine.register(*register)))
+            .collect();
+        writeln!(output, "{}", registers.join(", "))?;
+        let breakpoints: Vec<String> = self.breakpoints.iter().map(|pc| pc.to_string()).collect();
+        writeln!(output, "breakpoints: {}", breakpoints.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+
+    fn session(script: &str) -> String {
+        let mut debugger = Debugger::new(Machine::new(&EXAMPLE1)).with_history_length(5);
+        let mut output: Vec<u8> = Vec::new();
+        debugger.run(script.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        assert_eq!(
+            session("break 6\ncontinue\ncontinue\nbacktrace 2\n"),
+            "breakpoint at 6
+breakpoint at 6
+    6: acc +1       acc 1
+error: infinite loop at 1, acc 5
+    1: acc +1       acc 5
+    4: jmp -3
+    3: acc +3
+"
+        );
+    }
+
+    #[test]
+    fn test_watch_and_step() {
+        assert_eq!(
+            session("watch\nc\nunwatch\nwatch 5\nc\nstep 2\n"),
+            "watching acc
+watchpoint: acc 0 -> 1
+    2: jmp +4       acc 1
+removed all watchpoints
+watching acc == 5
+watchpoint: acc 2 -> 5
+    4: jmp -3       acc 5
+error: infinite loop at 1, acc 5
+    1: acc +1       acc 5
+"
+        );
+    }
+
+    #[test]
+    fn test_patch() {
+        assert_eq!(
+            session("c\npatch 7 nop -4\nc\ns\nbt 1\nq\ninfo\n"),
+            "error: infinite loop at 1, acc 5
+    1: acc +1       acc 5
+    7: jmp -4 -> nop -4
+terminated, acc 13
+terminated, acc 13
+    8: acc +6
+"
+        );
+    }
+
+    #[test]
+    fn test_rewind() {
+        assert_eq!(
+            session("c\nlastchange\nback 2\nbt\nrewind 2\nbt\nrewind 9\nc\n"),
+            "error: infinite loop at 1, acc 5
+    1: acc +1       acc 5
+step 5,     3: acc 2 -> 5
+at step 5
+    3: acc +3       acc 2
+    7: jmp -4
+    6: acc +1
+    2: jmp +4
+    1: acc +1
+    0: nop +0
+at step 2
+    2: jmp +4       acc 1
+    1: acc +1
+    0: nop +0
+error: expected a step up to 2
+error: infinite loop at 1, acc 5
+    1: acc +1       acc 5
+"
+        );
+    }
+
+    #[test]
+    fn test_list_and_info() {
+        assert_eq!(
+            session("b 2\nlist 1\ni\nfoo\nb 20\nlist 18446744073709551615\nlist 9\n"),
+            "breakpoint at 2
+>    0: nop +0
+     1: acc +1
+ *   2: jmp +4
+     3: acc +3
+     4: jmp -3
+pc 0, executed 0
+acc 0, a 0, b 0, c 0, d 0
+breakpoints: 2
+error: unknown command foo, try help
+error: expected a pc inside the program
+error: expected a pc inside the program
+     6: acc +1
+     7: jmp -4
+     8: acc +6
+"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use super::bytecode::decode;
+use super::cfg::{ControlFlowGraph, Termination};
+use super::compiled::{CompiledProgram, Executor};
+use super::generate::{generate, GeneratorConfig, Mode, Rng};
+use super::optimizer::{optimize, Pass};
+use super::{
+    disassemble, disassemble_with_labels, execute_program, load_bytecode, parse_program,
+    write_bytecode, ExitError, Instruction,
+};
+
+// Checks shared by the fuzz targets in gameboy/fuzz and the tests below. They
+// panic when an invariant does not hold, which is what the fuzzer looks for.
+
+// Whatever the input, parsing must not panic, and what parses must come back
+// the same after printing it.
+pub fn check_parse(data: &[u8]) {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    for line in text.lines() {
+        if let Ok(instruction) = Instruction::parse(line) {
+            let printed = instruction.to_string();
+            assert_eq!(
+                Instruction::parse(&printed).ok(),
+                Some(instruction),
+                "{:?}",
+                line
+            );
+        }
+    }
+    if let Ok(program) = parse_program(text) {
+        assert_eq!(parse_program(&disassemble(&program)).ok(), Some(program));
+    }
+}
+
+// Loading arbitrary bytes as bytecode must fail cleanly, and what loads must
+// be written back the same.
+pub fn check_bytecode(data: &[u8]) {
+    if let Ok(program) = load_bytecode(data) {
+        let mut bytes: Vec<u8> = Vec::new();
+        write_bytecode(&program, &mut bytes).unwrap();
+        assert_eq!(load_bytecode(&bytes[..]).unwrap(), program);
+    }
+}
+
+fn same_behaviour(left: &Result<i64, ExitError>, right: &Result<i64, ExitError>) -> bool {
+    match (left, right) {
+        (Err(ExitError::InfiniteLoop(left, _)), Err(ExitError::InfiniteLoop(right, _))) => {
+            left == right
+        }
+        (Err(left), Err(right)) => std::mem::discriminant(left) == std::mem::discriminant(right),
+        _ => left.as_ref().ok() == right.as_ref().ok(),
+    }
+}
+
+// The input picks a generated program, which is then run through everything
+// that has to agree with execute_program.
+pub fn check_execute(data: &[u8]) {
+    let mut seed = [0u8; 8];
+    for (index, byte) in data.iter().enumerate() {
+        seed[index % 8] ^= byte;
+    }
+    let mut rng = Rng::new(u64::from_le_bytes(seed));
+    let mode = match rng.below(3) {
+        0 => Mode::Any,
+        1 => Mode::Terminating,
+        _ => Mode::Looping,
+    };
+    let config = GeneratorConfig {
+        len: rng.below(64) as usize,
+        jump_density: rng.below(101) as f64 / 100.0,
+        max_operand: 1 << rng.below(63),
+        mode,
+    };
+    let program = generate(&config, &mut rng);
+    let result = execute_program(&program);
+
+    match mode {
+        Mode::Terminating => assert!(result.is_ok(), "{:?}", program),
+        Mode::Looping if !program.is_empty() => assert!(
+            matches!(result, Err(ExitError::InfiniteLoop(_, _))),
+            "{:?}",
+            program
+        ),
+        _ => (),
+    }
+
+    let termination = ControlFlowGraph::new(&program).termination();
+    let expected = match result {
+        Ok(_) => Termination::Terminates,
+        Err(ExitError::InfiniteLoop(_, _)) => Termination::Loops,
+        _ => Termination::Faults,
+    };
+    assert_eq!(termination, expected, "{:?}", program);
+
+    let compiled = CompiledProgram::compile(&program).unwrap();
+    assert_eq!(Executor::new().run(&compiled), result, "{:?}", program);
+
+    let optimized = optimize(&program, &Pass::ALL);
+    assert!(
+        same_behaviour(&execute_program(&optimized), &result),
+        "{:?}",
+        program
+    );
+}
+
+// Any program, with whatever operands, must run, analyse, compile, optimize
+// and disassemble without panicking, and the results must agree where they
+// can. The control flow graph does not model run time errors, so it only
+// rules out the outcomes it has proven impossible.
+fn check_program(program: &[Instruction]) {
+    let result = execute_program(program);
+
+    let termination = ControlFlowGraph::new(program).termination();
+    let impossible = matches!(
+        (termination, &result),
+        (Termination::Terminates, Err(ExitError::InfiniteLoop(_, _)))
+            | (
+                Termination::Terminates,
+                Err(ExitError::JumpOutOfBounds(_, _))
+            )
+            | (Termination::Loops, Ok(_))
+            | (Termination::Loops, Err(ExitError::JumpOutOfBounds(_, _)))
+            | (Termination::Faults, Ok(_))
+            | (Termination::Faults, Err(ExitError::InfiniteLoop(_, _)))
+    );
+    assert!(
+        !impossible,
+        "{:?} but {:?}: {:?}",
+        termination, result, program
+    );
+
+    if let Ok(compiled) = CompiledProgram::compile(program) {
+        assert_eq!(Executor::new().run(&compiled), result, "{:?}", program);
+    }
+
+    let optimized = optimize(program, &Pass::ALL);
+    assert!(
+        same_behaviour(&execute_program(&optimized), &result),
+        "{:?}",
+        program
+    );
+
+    assert_eq!(
+        parse_program(&disassemble_with_labels(program)).ok(),
+        Some(program.to_vec())
+    );
+}
+
+// The input is loaded as bytecode. Since the checksum keeps out nearly all
+// raw bytes, it is also decoded as a bare body, the first byte being the
+// number of instructions.
+pub fn check_decoded(data: &[u8]) {
+    if let Ok(program) = load_bytecode(data) {
+        check_program(&program);
+    }
+    if let Some((count, body)) = data.split_first() {
+        if let Ok(program) = decode(body, *count as usize) {
+            check_program(&program);
+        }
+    }
+}
+
+#[cfg(test)]
+mod fuzz_tests {
+    use super::*;
+
+    #[test]
+    fn test_check_parse() {
+        let inputs: [&[u8]; 8] = [
+            b"nop +0\nacc +1\njmp -1\n",
+            b"acc +99999999999999999999999999\n",
+            b"jmp -9223372036854775808\n",
+            b"loop: jmp loop\n.dialect 2\n",
+            b".dialect 2\nset a +3\nmul acc a\njnz a -1\nout acc\n",
+            b"acc\n\n   \t# only a comment\nnop +\n",
+            b"\xff\xfe\n",
+            b"x: y: z: jmp x\n",
+        ];
+        for input in inputs.iter() {
+            check_parse(input);
+        }
+
+        let mut rng = Rng::new(14);
+        let alphabet = b"nopacjmdluiets +-0123456789:#\n.";
+        for _ in 0..2000 {
+            let len = rng.below(40) as usize;
+            let input: Vec<u8> = (0..len)
+                .map(|_| alphabet[rng.below(alphabet.len() as u64) as usize])
+                .collect();
+            check_parse(&input);
+        }
+    }
+
+    #[test]
+    fn test_check_bytecode() {
+        let mut rng = Rng::new(9);
+        let mut valid: Vec<u8> = Vec::new();
+        write_bytecode(&generate(&GeneratorConfig::default(), &mut rng), &mut valid).unwrap();
+        check_bytecode(&valid);
+        for _ in 0..2000 {
+            let mut input = valid.clone();
+            let index = rng.below(input.len() as u64) as usize;
+            input[index] = rng.next_u64() as u8;
+            input.truncate(rng.below(input.len() as u64 + 1) as usize);
+            check_bytecode(&input);
+        }
+    }
+
+    #[test]
+    fn test_check_decoded() {
+        let extremes = [
+            vec![Instruction::Nop(0), Instruction::Jmp(isize::MAX)],
+            vec![Instruction::Jmp(isize::MIN), Instruction::Nop(i64::MIN)],
+            vec![Instruction::Acc(i64::MAX), Instruction::Acc(i64::MAX)],
+            crate::parse_program(".dialect 2\nset a +1\njnz a +9223372036854775807\n").unwrap(),
+        ];
+        for program in extremes.iter() {
+            let mut bytes: Vec<u8> = Vec::new();
+            write_bytecode(program, &mut bytes).unwrap();
+            check_decoded(&bytes);
+        }
+
+        let mut rng = Rng::new(21);
+        let mut decoded = 0;
+        for _ in 0..5000 {
+            let len = rng.below(48) as usize;
+            let mut input: Vec<u8> = vec![rng.below(8) as u8];
+            for _ in 0..len {
+                // Mostly opcodes, tags, registers and small numbers, with the
+                // occasional long varint for an oversized operand.
+                match rng.below(8) {
+                    0 => input.extend_from_slice(&[0xff; 9]),
+                    1 => input.push(rng.next_u64() as u8),
+                    _ => input.push(rng.below(10) as u8),
+                }
+            }
+            if decode(&input[1..], input[0] as usize).is_ok() {
+                decoded += 1;
+            }
+            check_decoded(&input);
+        }
+        assert!(decoded > 0);
+    }
+
+    #[test]
+    fn test_check_execute() {
+        let mut rng = Rng::new(3);
+        for _ in 0..2000 {
+            let input = rng.next_u64().to_le_bytes();
+            check_execute(&input);
+        }
+    }
+}
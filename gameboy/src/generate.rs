@@ -0,0 +1,176 @@
+use super::Instruction;
+
+// Random version 1 programs for tests and fuzzing. Every jump lands inside
+// 0..=len, and operands are kept small enough that no run can overflow the
+// accumulator.
+
+// xorshift64*, good enough for tests and needs no dependencies.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Rng((seed ^ 0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // A number in 0..bound, bound must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    // A number in low..=high.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128 + 1) as u128;
+        (low as i128 + (self.next_u64() as u128 % span) as i128) as i64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Any,
+    // Jumps only go forward, so the program runs off the end.
+    Terminating,
+    // Like Terminating, but the last instruction on the way out jumps back.
+    Looping,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    pub len: usize,
+    // The share of instructions that are jumps, the rest are split between
+    // acc and nop two to one.
+    pub jump_density: f64,
+    pub max_operand: i64,
+    pub mode: Mode,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            len: 32,
+            jump_density: 0.3,
+            max_operand: 100,
+            mode: Mode::Any,
+        }
+    }
+}
+
+pub fn generate(config: &GeneratorConfig, rng: &mut Rng) -> Vec<Instruction> {
+    let len = config.len;
+    let max_operand = config.max_operand.min(i64::MAX / (len as i64 + 1)).max(0);
+    let mut program: Vec<Instruction> = (0..len)
+        .map(|pc| {
+            if rng.chance(config.jump_density) {
+                let low = match config.mode {
+                    Mode::Any => -(pc as i64),
+                    Mode::Terminating | Mode::Looping => 1,
+                };
+                Instruction::Jmp(rng.range(low, (len - pc) as i64) as isize)
+            } else if rng.below(3) == 0 {
+                Instruction::Nop(rng.range(-max_operand, max_operand))
+            } else {
+                Instruction::Acc(rng.range(-max_operand, max_operand))
+            }
+        })
+        .collect();
+
+    if config.mode == Mode::Looping && len > 0 {
+        // Forward jumps visit every instruction at most once, so jumping from
+        // the last one on the path back to any earlier one never gets out.
+        let mut path: Vec<usize> = Vec::new();
+        let mut pc: usize = 0;
+        while pc < len {
+            path.push(pc);
+            pc = match program[pc] {
+                Instruction::Jmp(offset) => (pc as isize + offset) as usize,
+                _ => pc + 1,
+            };
+        }
+        let last = *path.last().unwrap();
+        let target = path[rng.below(path.len() as u64) as usize];
+        program[last] = Instruction::Jmp(target as isize - last as isize);
+    }
+    program
+}
+
+#[cfg(test)]
+mod generate_tests {
+    use super::*;
+    use crate::{execute_program, ExitError};
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(0);
+        let mut other = Rng::new(0);
+        for _ in 0..100 {
+            assert_eq!(rng.next_u64(), other.next_u64());
+            let value = rng.range(-3, 3);
+            assert!((-3..=3).contains(&value));
+            other.range(-3, 3);
+        }
+        assert!(!rng.chance(0.0));
+        assert!(rng.chance(1.0));
+        assert_eq!(rng.range(i64::MIN, i64::MIN), i64::MIN);
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut rng = Rng::new(8);
+        for len in 0..40 {
+            for jump_density in [0.0, 0.5, 1.0].iter() {
+                let mut config = GeneratorConfig {
+                    len,
+                    jump_density: *jump_density,
+                    max_operand: i64::MAX,
+                    mode: Mode::Terminating,
+                };
+                let program = generate(&config, &mut rng);
+                assert_eq!(program.len(), len);
+                assert!(execute_program(&program).is_ok());
+
+                config.mode = Mode::Looping;
+                let program = generate(&config, &mut rng);
+                if len > 0 {
+                    assert!(matches!(
+                        execute_program(&program),
+                        Err(ExitError::InfiniteLoop(_, _))
+                    ));
+                }
+
+                config.mode = Mode::Any;
+                let program = generate(&config, &mut rng);
+                assert!(!matches!(
+                    execute_program(&program),
+                    Err(ExitError::JumpOutOfBounds(_, _)) | Err(ExitError::ArithmeticOverflow(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_jump_density() {
+        let config = GeneratorConfig {
+            len: 10_000,
+            jump_density: 0.25,
+            ..GeneratorConfig::default()
+        };
+        let program = generate(&config, &mut Rng::new(1));
+        let jumps = program
+            .iter()
+            .filter(|instruction| matches!(instruction, Instruction::Jmp(_)))
+            .count();
+        assert!((2000..3000).contains(&jumps), "{}", jumps);
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+
+// Channels for the `inp` and `out` instructions of the version 2 dialect.
+
+// Peek returns what the next read would, without consuming it, so a step
+// that fails after looking at its input can leave the input untouched.
+pub trait Input {
+    fn peek(&self) -> Option<i64>;
+    fn read(&mut self) -> Option<i64>;
+}
+
+pub trait Output {
+    fn write(&mut self, value: i64);
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn peek(&self) -> Option<i64> {
+        (**self).peek()
+    }
+
+    fn read(&mut self) -> Option<i64> {
+        (**self).read()
+    }
+}
+
+impl<T: Output + ?Sized> Output for &mut T {
+    fn write(&mut self, value: i64) {
+        (**self).write(value)
+    }
+}
+
+impl Input for VecDeque<i64> {
+    fn peek(&self) -> Option<i64> {
+        self.front().copied()
+    }
+
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+pub struct NoInput;
+
+impl Input for NoInput {
+    fn peek(&self) -> Option<i64> {
+        None
+    }
+
+    fn read(&mut self) -> Option<i64> {
+        None
+    }
+}
+
+pub struct NoOutput;
+
+impl Output for NoOutput {
+    fn write(&mut self, _value: i64) {}
+}
@@ -2,101 +2,244 @@
 extern crate lazy_static;
 extern crate regex;
 
+mod asm;
+pub mod bytecode;
+pub mod cfg;
+pub mod compiled;
+pub mod debugger;
+// Checks and program generators for the cargo fuzz targets, kept out of
+// normal builds.
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod generate;
+pub mod io;
+mod machine;
+pub mod optimizer;
+pub mod profile;
+mod repair;
+pub mod symbolic;
+pub mod trace;
+
+pub use asm::{disassemble, disassemble_with_labels, ParseError, ParseErrorKind};
+pub use bytecode::{load_bytecode, write_bytecode, BytecodeError};
+pub use machine::{AccumulatorChange, Limits, Machine, Status};
+pub use repair::{find_repairs, Mutation, Repair};
+use std::fmt;
+use std::time::Duration;
+pub use trace::Tracer;
+
+// Programs start out in the original three-opcode dialect. Version 2 adds
+// registers, arithmetic, conditional jumps and I/O, and is only accepted by
+// the assembler after a `.dialect 2` directive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dialect {
+    V1,
+    V2,
+}
+
+// The accumulator doubles as a register in the version 2 dialect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Register {
+    Acc,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Register {
+    pub const ALL: [Register; 5] = [
+        Register::Acc,
+        Register::A,
+        Register::B,
+        Register::C,
+        Register::D,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Register::Acc => "acc",
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Register> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|register| register.name() == s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Immediate(i64),
+    Register(Register),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
     Nop(i64),
     Acc(i64),
     Jmp(isize),
+    Set(Register, Operand),
+    Add(Register, Operand),
+    Mul(Register, Operand),
+    Jz(Register, isize),
+    Jnz(Register, isize),
+    Inp(Register),
+    Out(Operand),
 }
 
-#[allow(dead_code)]
 impl Instruction {
-    pub fn parse(s: &str) -> Option<Instruction> {
-        lazy_static! {
-            static ref INSTRUCTION_RE: regex::Regex =
-                regex::Regex::new(r"([a-z]+) ([+-]\d*)").unwrap();
+    pub fn parse(s: &str) -> Result<Instruction, ParseError> {
+        asm::parse_instruction(s, Dialect::V1)
+    }
+
+    pub fn parse_with_dialect(s: &str, dialect: Dialect) -> Result<Instruction, ParseError> {
+        asm::parse_instruction(s, dialect)
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        match self {
+            Instruction::Nop(_) | Instruction::Acc(_) | Instruction::Jmp(_) => Dialect::V1,
+            _ => Dialect::V2,
         }
+    }
 
-        if let Some(captures) = INSTRUCTION_RE.captures(s) {
-            if captures.len() != 3 {
-                return None;
-            }
+    pub fn is_conditional(&self) -> bool {
+        matches!(self, Instruction::Jz(_, _) | Instruction::Jnz(_, _))
+    }
 
-            let name = captures.get(1).unwrap().as_str();
-            let arg = captures.get(2).unwrap().as_str().parse::<i64>().unwrap();
-            match name {
-                "nop" => Some(Self::Nop(arg)),
-                "acc" => Some(Self::Acc(arg)),
-                "jmp" => Some(Self::Jmp(arg as isize)),
-                _ => None,
+    // The offset of a jump, taken or not.
+    pub fn jump_offset(&self) -> Option<isize> {
+        match self {
+            Instruction::Jmp(offset) | Instruction::Jz(_, offset) | Instruction::Jnz(_, offset) => {
+                Some(*offset)
             }
-        } else {
-            None
+            _ => None,
+        }
+    }
+
+    pub fn with_jump_offset(&self, offset: isize) -> Instruction {
+        match self {
+            Instruction::Jmp(_) => Instruction::Jmp(offset),
+            Instruction::Jz(register, _) => Instruction::Jz(*register, offset),
+            Instruction::Jnz(register, _) => Instruction::Jnz(*register, offset),
+            _ => self.clone(),
         }
     }
 }
 
-#[derive(Debug)]
+pub fn program_dialect(program: &[Instruction]) -> Dialect {
+    program
+        .iter()
+        .map(Instruction::dialect)
+        .max()
+        .unwrap_or(Dialect::V1)
+}
+
+// The pc in each variant is the instruction that could not be executed.
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExitError {
+    // Accumulator and pc when an instruction was about to run a second time.
     InfiniteLoop(i64, usize),
-    InvalidPC(isize, usize),
-}
-
-pub fn parse_program(input: &str) -> Result<Vec<Instruction>, usize> {
-    input
-        .lines()
-        .enumerate()
-        .filter(|(_, line)| line.len() > 0)
-        .map(|(line_index, line)| {
-            println!("{}: {}", line_index + 1, line);
-            if let Some(instruction) = Instruction::parse(line) {
-                Ok(instruction)
-            } else {
-                Err(line_index)
-            }
-        })
-        .collect()
+    // Jump from the first pc to the second, which is outside of 0..=len.
+    JumpOutOfBounds(usize, isize),
+    InputExhausted(usize),
+    // The result of the instruction does not fit in an i64.
+    ArithmeticOverflow(usize),
+    // Steps executed so far and pc.
+    StepLimitExceeded(usize, usize),
+    // The accumulator value the instruction would have written, and pc.
+    AccumulatorLimitExceeded(i64, usize),
+    // Time spent running so far and pc.
+    TimeLimitExceeded(Duration, usize),
 }
 
-pub fn execute_program(program: &Vec<Instruction>) -> Result<i64, ExitError> {
-    let mut visited: Vec<bool> = Vec::with_capacity(program.len());
-    for _ in 0..program.len() {
-        visited.push(false);
-    }
-    let mut accumulator: i64 = 0;
-    let mut pos: usize = 0;
-    while pos < program.len() {
-        match visited.get(pos) {
-            None => break,
-            Some(visited) => {
-                if *visited {
-                    return Err(ExitError::InfiniteLoop(accumulator, pos));
-                }
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitError::InfiniteLoop(accumulator, pc) => {
+                write!(f, "infinite loop at {}, acc {}", pc, accumulator)
             }
-        }
-        visited[pos] = true;
-        println!("{:?} [{}]", program.get(pos), pos);
-        let offset: isize = match program.get(pos) {
-            Some(Instruction::Nop(_)) => 1,
-            Some(Instruction::Acc(number)) => {
-                accumulator += *number;
-                1
+            ExitError::JumpOutOfBounds(from, to) => {
+                write!(f, "jump from {} to {} is out of bounds", from, to)
             }
-            Some(Instruction::Jmp(offset)) => *offset,
-            None => {
-                return Err(ExitError::InvalidPC(pos as isize, pos));
+            ExitError::InputExhausted(pc) => write!(f, "input exhausted at {}", pc),
+            ExitError::ArithmeticOverflow(pc) => write!(f, "arithmetic overflow at {}", pc),
+            ExitError::StepLimitExceeded(steps, pc) => {
+                write!(f, "step limit exceeded at {} after {} steps", pc, steps)
+            }
+            ExitError::AccumulatorLimitExceeded(accumulator, pc) => write!(
+                f,
+                "accumulator limit exceeded at {}, acc would be {}",
+                pc, accumulator
+            ),
+            ExitError::TimeLimitExceeded(elapsed, pc) => {
+                write!(f, "time limit exceeded at {} after {:?}", pc, elapsed)
             }
-        };
-        let next_pos: isize = pos as isize + offset;
-        if next_pos < 0 {
-            return Err(ExitError::InvalidPC(next_pos, pos));
-        } else if next_pos as usize > program.len() {
-            return Err(ExitError::InvalidPC(next_pos, pos));
         }
-        pos = next_pos as usize;
     }
+}
+
+pub fn parse_program(input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    asm::assemble(input, Dialect::V1)
+}
+
+pub fn parse_program_with_dialect(
+    input: &str,
+    dialect: Dialect,
+) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    asm::assemble(input, dialect)
+}
+
+// A program terminates when execution continues at exactly program.len(),
+// either by running past the last instruction or by jumping there. Any other
+// target outside of the program is a JumpOutOfBounds error, and an empty
+// program terminates right away with an accumulator of 0.
+pub fn execute_program(program: &[Instruction]) -> Result<i64, ExitError> {
+    Machine::new(program).run()
+}
+
+pub fn execute_program_with_limits(
+    program: &[Instruction],
+    limits: Limits,
+) -> Result<i64, ExitError> {
+    Machine::new(program).with_limits(limits).run()
+}
+
+pub fn execute_program_with_tracer<T: Tracer>(
+    program: &[Instruction],
+    tracer: T,
+) -> Result<i64, ExitError> {
+    Machine::new(program).with_tracer(tracer).run()
+}
+
+// The example program from the puzzle, shared by the tests.
+#[cfg(test)]
+mod examples {
+    use super::Instruction;
 
-    Ok(accumulator)
+    pub const EXAMPLE1: [Instruction; 9] = [
+        Instruction::Nop(0),
+        Instruction::Acc(1),
+        Instruction::Jmp(4),
+        Instruction::Acc(3),
+        Instruction::Jmp(-3),
+        Instruction::Acc(-99),
+        Instruction::Acc(1),
+        Instruction::Jmp(-4),
+        Instruction::Acc(6),
+    ];
 }
 
 #[cfg(test)]
@@ -131,7 +274,56 @@ acc +6
         let mut instruction_it = instructions.iter();
         for line in EXAMPLE1.lines().skip(1) {
             let instruction = instruction_it.next();
-            assert_eq!(Instruction::parse(line).as_ref(), instruction);
+            assert_eq!(Instruction::parse(line).ok().as_ref(), instruction);
         }
     }
+
+    #[test]
+    fn test_termination_contract() {
+        assert_eq!(execute_program(&[]), Ok(0));
+        assert_eq!(execute_program(&[Instruction::Acc(2)]), Ok(2));
+        let jump_to_end = [
+            Instruction::Acc(1),
+            Instruction::Jmp(2),
+            Instruction::Acc(5),
+        ];
+        assert_eq!(execute_program(&jump_to_end), Ok(1));
+        let jump_past_end = [
+            Instruction::Acc(1),
+            Instruction::Jmp(3),
+            Instruction::Acc(5),
+        ];
+        assert_eq!(
+            execute_program(&jump_past_end),
+            Err(ExitError::JumpOutOfBounds(1, 4))
+        );
+        let jump_before_start = [Instruction::Jmp(-1)];
+        assert_eq!(
+            execute_program(&jump_before_start),
+            Err(ExitError::JumpOutOfBounds(0, -1))
+        );
+        let jump_overflow = [Instruction::Nop(0), Instruction::Jmp(isize::MAX)];
+        assert_eq!(
+            execute_program(&jump_overflow),
+            Err(ExitError::JumpOutOfBounds(1, isize::MAX))
+        );
+    }
+
+    #[test]
+    fn test_execute_with_limits() {
+        let program = [Instruction::Acc(1), Instruction::Acc(1)];
+        let limits = Limits {
+            max_steps: Some(2),
+            ..Limits::default()
+        };
+        assert_eq!(execute_program_with_limits(&program, limits.clone()), Ok(2));
+        let limits = Limits {
+            max_steps: Some(1),
+            ..limits
+        };
+        assert_eq!(
+            execute_program_with_limits(&program, limits),
+            Err(ExitError::StepLimitExceeded(1, 1))
+        );
+    }
 }
@@ -0,0 +1,598 @@
+use super::io::{Input, NoInput, NoOutput, Output};
+use super::trace::{Event, NoopTracer, Tracer};
+use super::{ExitError, Instruction, Operand, Register};
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Terminated,
+}
+
+// Unset limits are not checked. The accumulator limit bounds its magnitude,
+// and the time budget is measured from the first step and checked every
+// TIME_CHECK_INTERVAL steps to keep the clock out of the hot loop.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<usize>,
+    pub max_accumulator: Option<i64>,
+    pub time_budget: Option<Duration>,
+}
+
+const TIME_CHECK_INTERVAL: usize = 1024;
+
+// The step is the number of instructions executed before the change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccumulatorChange {
+    pub step: usize,
+    pub pc: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// What a step changed, enough to put the machine back to where it was.
+struct Undo {
+    pc: usize,
+    visited: bool,
+    write: Option<(Register, i64, i64)>,
+}
+
+// Without conditional jumps the control flow does not depend on any data, so
+// running an instruction a second time means the program loops forever.
+// Programs with conditional jumps may revisit instructions legitimately and
+// are not checked for loops.
+pub struct Machine<'a> {
+    program: Cow<'a, [Instruction]>,
+    pc: usize,
+    registers: [i64; 5],
+    executed: usize,
+    visited: Vec<bool>,
+    detect_loops: bool,
+    limits: Limits,
+    started: Option<Instant>,
+    undo_log: Option<Vec<Undo>>,
+    tracer: Box<dyn Tracer + 'a>,
+    input: Box<dyn Input + 'a>,
+    output: Box<dyn Output + 'a>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Self {
+            program: Cow::Borrowed(program),
+            pc: 0,
+            registers: [0; 5],
+            executed: 0,
+            visited: vec![false; program.len()],
+            detect_loops: !program.iter().any(Instruction::is_conditional),
+            limits: Limits::default(),
+            started: None,
+            undo_log: None,
+            tracer: Box::new(NoopTracer),
+            input: Box::new(NoInput),
+            output: Box::new(NoOutput),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Records every step so that it can be undone. Input that was read and
+    // output that was written stay consumed and written.
+    pub fn with_undo_log(mut self) -> Self {
+        self.undo_log = Some(Vec::new());
+        self
+    }
+
+    pub fn with_tracer<T: Tracer + 'a>(mut self, tracer: T) -> Self {
+        self.tracer = Box::new(tracer);
+        self
+    }
+
+    pub fn with_input<I: Input + 'a>(mut self, input: I) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn with_output<O: Output + 'a>(mut self, output: O) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn accumulator(&self) -> i64 {
+        self.registers[Register::Acc.index()]
+    }
+
+    pub fn register(&self, register: Register) -> i64 {
+        self.registers[register.index()]
+    }
+
+    pub fn executed(&self) -> usize {
+        self.executed
+    }
+
+    pub fn current(&self) -> Option<&Instruction> {
+        self.program.get(self.pc)
+    }
+
+    pub fn status(&self) -> Status {
+        if self.pc == self.program.len() {
+            Status::Terminated
+        } else {
+            Status::Running
+        }
+    }
+
+    // Replaces the instruction at pc, copying the program the first time, and
+    // returns the old one. What ran before the patch says nothing about the
+    // new program, so loop detection starts over from the current state.
+    pub fn patch(&mut self, pc: usize, instruction: Instruction) -> Option<Instruction> {
+        if pc >= self.program.len() {
+            return None;
+        }
+        let old = std::mem::replace(&mut self.program.to_mut()[pc], instruction);
+        self.detect_loops = !self.program.iter().any(Instruction::is_conditional);
+        self.visited.iter_mut().for_each(|visited| *visited = false);
+        Some(old)
+    }
+
+    // Executes the instruction at pc. A step that fails leaves the machine
+    // untouched, so stepping again reports the same error.
+    pub fn step(&mut self) -> Result<Status, ExitError> {
+        let pc = self.pc;
+        // A copy, since the program may be patched between steps.
+        let instruction = &match self.program.get(pc) {
+            Some(instruction) => instruction.clone(),
+            None => return Ok(Status::Terminated),
+        };
+        if self.detect_loops && self.visited[pc] {
+            self.tracer.trace(&Event::LoopDetected {
+                pc,
+                accumulator: self.accumulator(),
+            });
+            return Err(ExitError::InfiniteLoop(self.accumulator(), pc));
+        }
+        self.check_limits(pc)?;
+        self.tracer.trace(&Event::Fetch { pc, instruction });
+        let result = self.execute(pc, instruction);
+        if let Err(error) = &result {
+            self.tracer.trace(&Event::Fault { pc, error });
+        }
+        result
+    }
+
+    // The fetched instruction's part of step.
+    fn execute(&mut self, pc: usize, instruction: &Instruction) -> Result<Status, ExitError> {
+        let registers = self.registers;
+        let get = |register: &Register| registers[register.index()];
+        let value = |operand: &Operand| match operand {
+            Operand::Immediate(value) => *value,
+            Operand::Register(register) => get(register),
+        };
+
+        let jump: Option<isize> = match instruction {
+            Instruction::Jmp(offset) => Some(*offset),
+            Instruction::Jz(register, offset) if get(register) == 0 => Some(*offset),
+            Instruction::Jnz(register, offset) if get(register) != 0 => Some(*offset),
+            _ => None,
+        };
+        let offset = jump.unwrap_or(1);
+        let next_pc: isize = match (pc as isize).checked_add(offset) {
+            Some(next_pc) if next_pc >= 0 && next_pc as usize <= self.program.len() => next_pc,
+            // A target that does not fit in an isize is reported saturated.
+            _ => {
+                return Err(ExitError::JumpOutOfBounds(
+                    pc,
+                    (pc as isize).saturating_add(offset),
+                ))
+            }
+        };
+
+        let overflow = ExitError::ArithmeticOverflow(pc);
+        let write: Option<(Register, i64)> = match instruction {
+            Instruction::Acc(number) => Some((
+                Register::Acc,
+                get(&Register::Acc).checked_add(*number).ok_or(overflow)?,
+            )),
+            Instruction::Set(register, operand) => Some((*register, value(operand))),
+            Instruction::Add(register, operand) => Some((
+                *register,
+                get(register).checked_add(value(operand)).ok_or(overflow)?,
+            )),
+            Instruction::Mul(register, operand) => Some((
+                *register,
+                get(register).checked_mul(value(operand)).ok_or(overflow)?,
+            )),
+            // Only consumed once the step can no longer fail.
+            Instruction::Inp(register) => match self.input.peek() {
+                Some(value) => Some((*register, value)),
+                None => return Err(ExitError::InputExhausted(pc)),
+            },
+            _ => None,
+        };
+
+        if let (Some((Register::Acc, new)), Some(max)) = (write, self.limits.max_accumulator) {
+            if new.unsigned_abs() > max.unsigned_abs() {
+                return Err(ExitError::AccumulatorLimitExceeded(new, pc));
+            }
+        }
+
+        if let Instruction::Inp(_) = instruction {
+            self.input.read();
+        }
+        if let Some((register, new)) = write {
+            let old = self.registers[register.index()];
+            self.registers[register.index()] = new;
+            self.tracer.trace(&match register {
+                Register::Acc => Event::AccumulatorChanged { pc, old, new },
+                _ => Event::RegisterChanged {
+                    pc,
+                    register,
+                    old,
+                    new,
+                },
+            });
+        }
+        if let Instruction::Out(operand) = instruction {
+            let value = value(operand);
+            self.output.write(value);
+            self.tracer.trace(&Event::Output { pc, value });
+        }
+        if jump.is_some() {
+            self.tracer.trace(&Event::Jump {
+                from: pc,
+                to: next_pc as usize,
+            });
+        }
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(Undo {
+                pc,
+                visited: self.visited[pc],
+                write: write.map(|(register, new)| (register, registers[register.index()], new)),
+            });
+        }
+        self.visited[pc] = true;
+        self.executed += 1;
+        self.pc = next_pc as usize;
+        let status = self.status();
+        if status == Status::Terminated {
+            self.tracer.trace(&Event::Terminated {
+                accumulator: self.accumulator(),
+            });
+        }
+        Ok(status)
+    }
+
+    // Undoes the last step, returns false when there is nothing recorded to
+    // undo.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.undo_log.as_mut().and_then(|undo_log| undo_log.pop()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        if let Some((register, old, _)) = undo.write {
+            self.registers[register.index()] = old;
+        }
+        self.visited[undo.pc] = undo.visited;
+        self.executed -= 1;
+        self.pc = undo.pc;
+        true
+    }
+
+    // Steps back until exactly step instructions have been executed.
+    pub fn rewind_to(&mut self, step: usize) -> bool {
+        let recorded = self.undo_log.as_ref().map_or(0, Vec::len);
+        if step > self.executed || self.executed - step > recorded {
+            return false;
+        }
+        while self.executed > step {
+            self.step_back();
+        }
+        true
+    }
+
+    // The pcs of the recorded steps, oldest first.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.undo_log.iter().flatten().map(|undo| undo.pc)
+    }
+
+    pub fn last_accumulator_change(&self) -> Option<AccumulatorChange> {
+        let undo_log = self.undo_log.as_ref()?;
+        let first_step = self.executed - undo_log.len();
+        undo_log
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, undo)| match undo.write {
+                Some((Register::Acc, old, new)) => Some(AccumulatorChange {
+                    step: first_step + index,
+                    pc: undo.pc,
+                    old,
+                    new,
+                }),
+                _ => None,
+            })
+    }
+
+    fn check_limits(&mut self, pc: usize) -> Result<(), ExitError> {
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.executed >= max_steps {
+                return Err(ExitError::StepLimitExceeded(self.executed, pc));
+            }
+        }
+        if let Some(budget) = self.limits.time_budget {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.executed.is_multiple_of(TIME_CHECK_INTERVAL) {
+                let elapsed = started.elapsed();
+                if elapsed >= budget {
+                    return Err(ExitError::TimeLimitExceeded(elapsed, pc));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Steps at least once, then until the predicate holds or the program
+    // terminates.
+    pub fn run_until<P>(&mut self, mut predicate: P) -> Result<Status, ExitError>
+    where
+        P: FnMut(&Machine) -> bool,
+    {
+        loop {
+            let status = self.step()?;
+            if status == Status::Terminated || predicate(self) {
+                return Ok(status);
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> Result<i64, ExitError> {
+        self.run_until(|_| false)?;
+        Ok(self.accumulator())
+    }
+}
+
+#[cfg(test)]
+mod machine_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+
+    #[test]
+    fn test_step() {
+        let mut machine = Machine::new(&EXAMPLE1);
+        assert_eq!(machine.current(), Some(&Instruction::Nop(0)));
+        let expected: [(usize, i64); 7] = [(1, 0), (2, 1), (6, 1), (7, 2), (3, 2), (4, 5), (1, 5)];
+        for (pc, accumulator) in expected.iter() {
+            assert_eq!(machine.step().unwrap(), Status::Running);
+            assert_eq!((machine.pc(), machine.accumulator()), (*pc, *accumulator));
+        }
+        assert_eq!(machine.executed(), 7);
+        assert!(matches!(machine.step(), Err(ExitError::InfiniteLoop(5, 1))));
+        assert!(matches!(machine.step(), Err(ExitError::InfiniteLoop(5, 1))));
+        assert_eq!(machine.executed(), 7);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut machine = Machine::new(&EXAMPLE1);
+        let status = machine.run_until(|m| m.pc() == 3).unwrap();
+        assert_eq!(status, Status::Running);
+        assert_eq!(
+            (machine.pc(), machine.accumulator(), machine.executed()),
+            (3, 2, 5)
+        );
+
+        let status = machine.run_until(|m| m.accumulator() > 2).unwrap();
+        assert_eq!(status, Status::Running);
+        assert_eq!((machine.pc(), machine.accumulator()), (4, 5));
+    }
+
+    #[test]
+    fn test_run() {
+        let mut program = EXAMPLE1.to_vec();
+        program[7] = Instruction::Nop(-4);
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run().unwrap(), 8);
+        assert_eq!(machine.status(), Status::Terminated);
+        assert_eq!(machine.step().unwrap(), Status::Terminated);
+        assert_eq!(machine.executed(), 6);
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut machine = Machine::new(&EXAMPLE1);
+        assert!(matches!(machine.run(), Err(ExitError::InfiniteLoop(5, 1))));
+        assert_eq!(
+            machine.patch(7, Instruction::Nop(-4)),
+            Some(Instruction::Jmp(-4))
+        );
+        assert_eq!(machine.patch(9, Instruction::Nop(0)), None);
+        assert_eq!(machine.run().unwrap(), 13);
+        assert_eq!(EXAMPLE1[7], Instruction::Jmp(-4));
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut machine = Machine::new(&EXAMPLE1).with_undo_log();
+        assert!(!machine.step_back());
+        assert!(matches!(machine.run(), Err(ExitError::InfiniteLoop(5, 1))));
+        assert_eq!(
+            machine.last_accumulator_change(),
+            Some(AccumulatorChange {
+                step: 5,
+                pc: 3,
+                old: 2,
+                new: 5
+            })
+        );
+
+        assert!(machine.step_back());
+        assert_eq!(
+            (machine.pc(), machine.accumulator(), machine.executed()),
+            (4, 5, 6)
+        );
+        assert!(machine.step_back());
+        assert_eq!(
+            (machine.pc(), machine.accumulator(), machine.executed()),
+            (3, 2, 5)
+        );
+        assert_eq!(machine.last_accumulator_change().unwrap().pc, 6);
+
+        assert!(!machine.rewind_to(6));
+        assert!(machine.rewind_to(2));
+        assert_eq!((machine.pc(), machine.accumulator()), (2, 1));
+        assert!(matches!(machine.run(), Err(ExitError::InfiniteLoop(5, 1))));
+        assert_eq!(machine.executed(), 7);
+
+        assert!(machine.rewind_to(0));
+        assert_eq!(machine.last_accumulator_change(), None);
+        assert!(matches!(machine.run(), Err(ExitError::InfiniteLoop(5, 1))));
+    }
+
+    #[test]
+    fn test_step_back_without_log() {
+        let mut machine = Machine::new(&EXAMPLE1);
+        machine.step().unwrap();
+        assert!(!machine.step_back());
+        assert!(!machine.rewind_to(0));
+        assert_eq!(machine.last_accumulator_change(), None);
+    }
+
+    #[test]
+    fn test_jump_out_of_bounds() {
+        let program = [Instruction::Acc(1), Instruction::Jmp(-2)];
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Err(ExitError::JumpOutOfBounds(1, -1)));
+        assert_eq!((machine.pc(), machine.accumulator()), (1, 1));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let limits = Limits {
+            max_steps: Some(3),
+            ..Limits::default()
+        };
+        let mut machine = Machine::new(&EXAMPLE1).with_limits(limits);
+        assert_eq!(machine.run(), Err(ExitError::StepLimitExceeded(3, 6)));
+        assert_eq!((machine.pc(), machine.executed()), (6, 3));
+    }
+
+    #[test]
+    fn test_accumulator_limit() {
+        let limits = Limits {
+            max_accumulator: Some(3),
+            ..Limits::default()
+        };
+        let program = [
+            Instruction::Acc(-3),
+            Instruction::Acc(6),
+            Instruction::Acc(1),
+        ];
+        let mut machine = Machine::new(&program).with_limits(limits);
+        assert_eq!(
+            machine.run(),
+            Err(ExitError::AccumulatorLimitExceeded(4, 2))
+        );
+        assert_eq!(machine.accumulator(), 3);
+    }
+
+    #[test]
+    fn test_arithmetic_overflow() {
+        let program = [Instruction::Acc(i64::MAX), Instruction::Acc(1)];
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Err(ExitError::ArithmeticOverflow(1)));
+        assert_eq!(machine.accumulator(), i64::MAX);
+
+        let program =
+            crate::parse_program(".dialect 2\nset b +4611686018427387904\nmul b +2\n").unwrap();
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Err(ExitError::ArithmeticOverflow(1)));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let limits = Limits {
+            time_budget: Some(Duration::from_millis(0)),
+            ..Limits::default()
+        };
+        let mut machine = Machine::new(&EXAMPLE1).with_limits(limits);
+        assert!(matches!(
+            machine.run(),
+            Err(ExitError::TimeLimitExceeded(_, 0))
+        ));
+        assert_eq!(machine.executed(), 0);
+    }
+
+    #[test]
+    fn test_dialect2() {
+        let program = crate::parse_program(
+            ".dialect 2
+        inp a
+loop:   jz a done
+        add acc a
+        add a -1
+        jmp loop
+done:   mul acc acc
+        out acc
+        out +7
+",
+        )
+        .unwrap();
+        let mut output: Vec<i64> = Vec::new();
+        let mut machine = Machine::new(&program)
+            .with_input(
+                vec![4]
+                    .into_iter()
+                    .collect::<std::collections::VecDeque<i64>>(),
+            )
+            .with_output(&mut output);
+        assert_eq!(machine.run().unwrap(), 100);
+        assert_eq!(machine.register(Register::A), 0);
+        assert_eq!(machine.executed(), 1 + 4 * 4 + 1 + 3);
+        drop(machine);
+        assert_eq!(output, vec![100, 7]);
+    }
+
+    #[test]
+    fn test_input_exhausted() {
+        let program = [Instruction::Acc(1), Instruction::Inp(Register::B)];
+        let mut machine = Machine::new(&program);
+        assert!(matches!(machine.run(), Err(ExitError::InputExhausted(1))));
+        assert_eq!((machine.pc(), machine.accumulator()), (1, 1));
+    }
+
+    #[test]
+    fn test_failed_input_is_not_consumed() {
+        let limits = Limits {
+            max_accumulator: Some(5),
+            ..Limits::default()
+        };
+        let program = [Instruction::Inp(Register::Acc)];
+        let mut input: std::collections::VecDeque<i64> = vec![10, 1].into_iter().collect();
+        let mut machine = Machine::new(&program)
+            .with_limits(limits)
+            .with_input(&mut input);
+        assert_eq!(
+            machine.step(),
+            Err(ExitError::AccumulatorLimitExceeded(10, 0))
+        );
+        assert_eq!(
+            machine.step(),
+            Err(ExitError::AccumulatorLimitExceeded(10, 0))
+        );
+        assert_eq!((machine.pc(), machine.accumulator()), (0, 0));
+        drop(machine);
+        assert_eq!(input, vec![10, 1]);
+    }
+}
@@ -0,0 +1,372 @@
+use super::cfg::ControlFlowGraph;
+use super::Instruction;
+
+// Every pass keeps what execute_program reports: the accumulator of a
+// program that terminates, the accumulator at which a loop is detected, and
+// the kind of any other error. Instruction positions and step counts are not
+// kept.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    FoldAcc,
+    RemoveNops,
+    EliminateUnreachable,
+    ThreadJumps,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 4] = [
+        Pass::ThreadJumps,
+        Pass::EliminateUnreachable,
+        Pass::RemoveNops,
+        Pass::FoldAcc,
+    ];
+
+    pub fn run(self, program: &[Instruction]) -> Vec<Instruction> {
+        match self {
+            Pass::FoldAcc => fold_acc(program),
+            Pass::RemoveNops => remove_nops(program),
+            Pass::EliminateUnreachable => eliminate_unreachable(program),
+            Pass::ThreadJumps => thread_jumps(program),
+        }
+    }
+}
+
+// None for instructions that do not jump, and for jumps whose target does not
+// fit in an isize, which are left alone.
+fn jump_target(pc: usize, instruction: &Instruction) -> Option<isize> {
+    instruction
+        .jump_offset()
+        .and_then(|offset| (pc as isize).checked_add(offset))
+}
+
+fn jump_targets(program: &[Instruction]) -> Vec<bool> {
+    let mut targets: Vec<bool> = vec![false; program.len() + 1];
+    for (pc, instruction) in program.iter().enumerate() {
+        if let Some(target) = jump_target(pc, instruction) {
+            if target >= 0 && target as usize <= program.len() {
+                targets[target as usize] = true;
+            }
+        }
+    }
+    targets
+}
+
+// Removes the marked instructions and moves jumps, and the arguments of nops
+// in case they are swapped into jumps later, along. A jump to a removed
+// instruction continues with the next one that is kept, and jumps outside of
+// the program stay outside.
+pub(crate) fn remove_instructions(program: &[Instruction], removed: &[bool]) -> Vec<Instruction> {
+    let mut removed_before: Vec<isize> = Vec::with_capacity(program.len() + 1);
+    let mut count: isize = 0;
+    for is_removed in removed.iter() {
+        removed_before.push(count);
+        if *is_removed {
+            count += 1;
+        }
+    }
+    removed_before.push(count);
+
+    let new_index = |index: isize| -> isize {
+        if index < 0 {
+            index
+        } else if index as usize >= removed_before.len() {
+            index - count
+        } else {
+            index - removed_before[index as usize]
+        }
+    };
+    // A target that overflows is far outside of the program and stays there.
+    let fix_offset = |pc: usize, offset: isize| match (pc as isize).checked_add(offset) {
+        Some(target) => new_index(target) - new_index(pc as isize),
+        None => offset,
+    };
+
+    program
+        .iter()
+        .enumerate()
+        .filter(|(pc, _)| !removed[*pc])
+        .map(|(pc, instruction)| match instruction {
+            Instruction::Nop(arg) => Instruction::Nop(fix_offset(pc, *arg as isize) as i64),
+            _ => match instruction.jump_offset() {
+                Some(offset) => instruction.with_jump_offset(fix_offset(pc, offset)),
+                None => instruction.clone(),
+            },
+        })
+        .collect()
+}
+
+// Only folds arguments of the same sign, since for those the sum overflows
+// exactly when one of the additions would.
+fn fold_acc(program: &[Instruction]) -> Vec<Instruction> {
+    let targets = jump_targets(program);
+    let mut folded = program.to_vec();
+    let mut removed: Vec<bool> = vec![false; program.len()];
+    let mut pc: usize = 0;
+    while pc + 1 < folded.len() {
+        if let (Instruction::Acc(first), Instruction::Acc(second)) = (&folded[pc], &folded[pc + 1])
+        {
+            let same_sign = (*first >= 0) == (*second >= 0);
+            if let (true, false, Some(sum)) =
+                (same_sign, targets[pc + 1], first.checked_add(*second))
+            {
+                folded[pc + 1] = Instruction::Acc(sum);
+                removed[pc] = true;
+            }
+        }
+        pc += 1;
+    }
+    // The sum is kept in the second instruction so that runs of more than two
+    // fold into the last one. Jumps to the first now continue at the second.
+    remove_instructions(&folded, &removed)
+}
+
+fn remove_nops(program: &[Instruction]) -> Vec<Instruction> {
+    let removed: Vec<bool> = program
+        .iter()
+        .map(|instruction| matches!(instruction, Instruction::Nop(_)))
+        .collect();
+    remove_instructions(program, &removed)
+}
+
+fn eliminate_unreachable(program: &[Instruction]) -> Vec<Instruction> {
+    let reachable = ControlFlowGraph::new(program).reachable();
+    let removed: Vec<bool> = (0..program.len()).map(|pc| !reachable[pc]).collect();
+    remove_instructions(program, &removed)
+}
+
+// Jumps to an unconditional jump go straight to where that one leads. Chains
+// that end in a cycle of jumps are left alone, there is nowhere to go.
+fn thread_jumps(program: &[Instruction]) -> Vec<Instruction> {
+    let unconditional = |target: isize| -> Option<isize> {
+        if target < 0 {
+            return None;
+        }
+        match program.get(target as usize) {
+            Some(Instruction::Jmp(offset)) => target.checked_add(*offset),
+            _ => None,
+        }
+    };
+
+    program
+        .iter()
+        .enumerate()
+        .map(|(pc, instruction)| {
+            let mut target = match jump_target(pc, instruction) {
+                Some(target) => target,
+                None => return instruction.clone(),
+            };
+            let mut seen: Vec<isize> = vec![pc as isize];
+            while let Some(next) = unconditional(target) {
+                seen.push(target);
+                if seen.contains(&next) {
+                    return instruction.clone();
+                }
+                target = next;
+            }
+            instruction.with_jump_offset(target - pc as isize)
+        })
+        .collect()
+}
+
+// Runs the passes in order, over and over until the program stops changing.
+pub fn optimize(program: &[Instruction], passes: &[Pass]) -> Vec<Instruction> {
+    let mut optimized = program.to_vec();
+    loop {
+        let before = optimized.clone();
+        for pass in passes {
+            optimized = pass.run(&optimized);
+        }
+        if optimized == before {
+            return optimized;
+        }
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+    use crate::generate::Rng;
+    use crate::{execute_program, ExitError, Register};
+
+    #[test]
+    fn test_fold_acc() {
+        let program = [
+            Instruction::Acc(1),
+            Instruction::Acc(2),
+            Instruction::Acc(3),
+            Instruction::Acc(-4),
+            Instruction::Acc(5),
+            Instruction::Jmp(-1),
+        ];
+        assert_eq!(
+            Pass::FoldAcc.run(&program),
+            vec![
+                Instruction::Acc(6),
+                Instruction::Acc(-4),
+                Instruction::Acc(5),
+                Instruction::Jmp(-1),
+            ]
+        );
+        let overflow = [Instruction::Acc(i64::MAX), Instruction::Acc(1)];
+        assert_eq!(Pass::FoldAcc.run(&overflow), overflow.to_vec());
+    }
+
+    #[test]
+    fn test_remove_nops() {
+        let program = [
+            Instruction::Nop(3),
+            Instruction::Jmp(2),
+            Instruction::Nop(0),
+            Instruction::Acc(1),
+            Instruction::Jmp(-3),
+            Instruction::Jmp(3),
+        ];
+        assert_eq!(
+            Pass::RemoveNops.run(&program),
+            vec![
+                Instruction::Jmp(1),
+                Instruction::Acc(1),
+                Instruction::Jmp(-2),
+                Instruction::Jmp(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eliminate_unreachable() {
+        assert_eq!(
+            Pass::EliminateUnreachable.run(&EXAMPLE1),
+            vec![
+                Instruction::Nop(0),
+                Instruction::Acc(1),
+                Instruction::Jmp(3),
+                Instruction::Acc(3),
+                Instruction::Jmp(-3),
+                Instruction::Acc(1),
+                Instruction::Jmp(-3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_jumps() {
+        let program = [
+            Instruction::Jmp(2),
+            Instruction::Jmp(0),
+            Instruction::Jmp(2),
+            Instruction::Jmp(-2),
+            Instruction::Jz(Register::A, 1),
+        ];
+        assert_eq!(
+            Pass::ThreadJumps.run(&program),
+            vec![
+                Instruction::Jmp(4),
+                Instruction::Jmp(0),
+                Instruction::Jmp(2),
+                Instruction::Jmp(-2),
+                Instruction::Jz(Register::A, 1),
+            ]
+        );
+        let cycle = [
+            Instruction::Jmp(2),
+            Instruction::Jmp(1),
+            Instruction::Jmp(-1),
+        ];
+        assert_eq!(Pass::ThreadJumps.run(&cycle), cycle.to_vec());
+    }
+
+    #[test]
+    fn test_optimize() {
+        let mut program = EXAMPLE1.to_vec();
+        program[7] = Instruction::Nop(-4);
+        let optimized = optimize(&program, &Pass::ALL);
+        assert_eq!(
+            optimized,
+            vec![
+                Instruction::Acc(1),
+                Instruction::Jmp(1),
+                Instruction::Acc(7),
+            ]
+        );
+        assert_eq!(execute_program(&optimized).unwrap(), 8);
+    }
+
+    // Unlike the generator, this also produces jumps out of the program.
+    fn random_program(rng: &mut Rng) -> Vec<Instruction> {
+        let len = 1 + rng.below(16) as i64;
+        (0..len)
+            .map(|_| {
+                let number = rng.range(-len - 1, len + 1);
+                match rng.below(3) {
+                    0 => Instruction::Nop(number),
+                    1 => Instruction::Acc(number),
+                    _ => Instruction::Jmp(number as isize),
+                }
+            })
+            .collect()
+    }
+
+    fn same_behaviour(left: &Result<i64, ExitError>, right: &Result<i64, ExitError>) -> bool {
+        match (left, right) {
+            (Ok(left), Ok(right)) => left == right,
+            (Err(ExitError::InfiniteLoop(left, _)), Err(ExitError::InfiniteLoop(right, _))) => {
+                left == right
+            }
+            (Err(left), Err(right)) => {
+                std::mem::discriminant(left) == std::mem::discriminant(right)
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_jump_overflow() {
+        let program = [
+            Instruction::Jmp(2),
+            Instruction::Acc(1),
+            Instruction::Nop(0),
+            Instruction::Jmp(isize::MAX),
+            Instruction::Nop(i64::MIN),
+        ];
+        let expected = execute_program(&program);
+        assert_eq!(expected, Err(ExitError::JumpOutOfBounds(3, isize::MAX)));
+        for pass in Pass::ALL.iter() {
+            let result = execute_program(&pass.run(&program));
+            assert!(same_behaviour(&expected, &result), "{:?}", pass);
+        }
+        let optimized = optimize(&program, &Pass::ALL);
+        assert!(same_behaviour(&expected, &execute_program(&optimized)));
+    }
+
+    #[test]
+    fn test_preserves_behaviour() {
+        let mut rng = Rng::new(12);
+        for _ in 0..5000 {
+            let program = random_program(&mut rng);
+            let expected = execute_program(&program);
+            for pass in Pass::ALL.iter() {
+                let result = execute_program(&pass.run(&program));
+                assert!(
+                    same_behaviour(&expected, &result),
+                    "{:?} on {:?}: {:?} != {:?}",
+                    pass,
+                    program,
+                    expected,
+                    result
+                );
+            }
+            let optimized = optimize(&program, &Pass::ALL);
+            assert!(optimized.len() <= program.len());
+            let result = execute_program(&optimized);
+            assert!(
+                same_behaviour(&expected, &result),
+                "{:?}: {:?} != {:?}",
+                program,
+                expected,
+                result
+            );
+        }
+    }
+}
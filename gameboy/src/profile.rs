@@ -0,0 +1,203 @@
+use super::asm::instruction_lines;
+use super::trace::{Event, Tracer};
+use super::{ExitError, Instruction, Machine};
+use std::fmt::Write;
+
+// Counts how often each instruction runs. A fetched instruction that fails
+// counts as a fault instead. The listings follow gcov: a count per
+// instruction, ##### for instructions that never ran and - for source lines
+// without an instruction.
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    hits: Vec<u64>,
+    faults: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn new(len: usize) -> Self {
+        Self {
+            hits: vec![0; len],
+            faults: vec![0; len],
+        }
+    }
+
+    pub fn hits(&self, pc: usize) -> u64 {
+        self.hits.get(pc).copied().unwrap_or(0)
+    }
+
+    pub fn faults(&self, pc: usize) -> u64 {
+        self.faults.get(pc).copied().unwrap_or(0)
+    }
+
+    pub fn never_executed(&self) -> Vec<usize> {
+        (0..self.hits.len())
+            .filter(|pc| self.hits[*pc] == 0)
+            .collect()
+    }
+
+    // Executed and total number of instructions.
+    pub fn coverage(&self) -> (usize, usize) {
+        let executed = self.hits.iter().filter(|hits| **hits > 0).count();
+        (executed, self.hits.len())
+    }
+
+    fn count(&self, pc: usize) -> String {
+        match self.hits(pc) {
+            0 => String::from("#####"),
+            hits => hits.to_string(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        let (executed, total) = self.coverage();
+        let percent = if total == 0 {
+            100.0
+        } else {
+            100.0 * executed as f64 / total as f64
+        };
+        format!(
+            "coverage: {} of {} instructions ({:.1}%)\n",
+            executed, total, percent
+        )
+    }
+
+    pub fn listing(&self, program: &[Instruction]) -> String {
+        let mut listing = String::new();
+        for (pc, instruction) in program.iter().enumerate() {
+            writeln!(listing, "{:>9}: {:5}: {}", self.count(pc), pc, instruction).unwrap();
+        }
+        listing.push_str(&self.summary());
+        listing
+    }
+
+    // Like listing, but next to the source the program was assembled from,
+    // comments and labels included.
+    pub fn annotate(&self, source: &str) -> String {
+        let lines = instruction_lines(source);
+        let mut next = lines.iter().enumerate().peekable();
+        let mut listing = String::new();
+        for (line_index, line) in source.lines().enumerate() {
+            let count = match next.peek() {
+                Some((pc, instruction_line)) if **instruction_line == line_index => {
+                    let count = self.count(*pc);
+                    next.next();
+                    count
+                }
+                _ => String::from("-"),
+            };
+            writeln!(listing, "{:>9}: {}", count, line).unwrap();
+        }
+        listing.push_str(&self.summary());
+        listing
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &Event) {
+        match event {
+            Event::Fetch { pc, .. } => {
+                if *pc >= self.hits.len() {
+                    self.hits.resize(pc + 1, 0);
+                    self.faults.resize(pc + 1, 0);
+                }
+                self.hits[*pc] += 1;
+            }
+            Event::Fault { pc, .. } => {
+                self.hits[*pc] -= 1;
+                self.faults[*pc] += 1;
+            }
+            _ => (),
+        }
+    }
+}
+
+pub fn profile(program: &[Instruction]) -> (Result<i64, ExitError>, Profiler) {
+    let mut profiler = Profiler::new(program.len());
+    let result = Machine::new(program).with_tracer(&mut profiler).run();
+    (result, profiler)
+}
+
+#[cfg(test)]
+mod profile_tests {
+    use super::*;
+
+    const SOURCE: &str = "# the example from day 8
+nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+loop: acc +1
+jmp -4   # back to the second acc
+acc +6
+";
+
+    #[test]
+    fn test_hits() {
+        let program = crate::parse_program(SOURCE).unwrap();
+        let (result, profiler) = profile(&program);
+        assert!(matches!(result, Err(ExitError::InfiniteLoop(5, 1))));
+        let hits: Vec<u64> = (0..program.len()).map(|pc| profiler.hits(pc)).collect();
+        assert_eq!(hits, vec![1, 1, 1, 1, 1, 0, 1, 1, 0]);
+        assert_eq!(profiler.never_executed(), vec![5, 8]);
+        assert_eq!(profiler.coverage(), (7, 9));
+    }
+
+    #[test]
+    fn test_counts() {
+        let program = crate::parse_program(
+            ".dialect 2\nset a +3\nloop: add acc a\nadd a -1\njnz a loop\nout acc\n",
+        )
+        .unwrap();
+        let (result, profiler) = profile(&program);
+        assert_eq!(result.unwrap(), 6);
+        assert_eq!(
+            profiler.listing(&program),
+            "        1:     0: set a +3
+        3:     1: add acc a
+        3:     2: add a -1
+        3:     3: jnz a -2
+        1:     4: out acc
+coverage: 5 of 5 instructions (100.0%)
+"
+        );
+    }
+
+    #[test]
+    fn test_faults() {
+        let program = [
+            Instruction::Acc(1),
+            Instruction::Jmp(5),
+            Instruction::Acc(2),
+        ];
+        let (result, profiler) = profile(&program);
+        assert!(matches!(result, Err(ExitError::JumpOutOfBounds(1, 6))));
+        assert_eq!((profiler.hits(0), profiler.hits(1)), (1, 0));
+        assert_eq!((profiler.faults(0), profiler.faults(1)), (0, 1));
+        assert_eq!(profiler.never_executed(), vec![1, 2]);
+        assert_eq!(profiler.coverage(), (1, 3));
+    }
+
+    #[test]
+    fn test_annotate() {
+        let program = crate::parse_program(SOURCE).unwrap();
+        let (_, profiler) = profile(&program);
+        assert_eq!(
+            profiler.annotate(SOURCE),
+            "        -: # the example from day 8
+        1: nop +0
+        1: acc +1
+        1: jmp +4
+        1: acc +3
+        1: jmp -3
+    #####: acc -99
+        1: loop: acc +1
+        1: jmp -4   # back to the second acc
+    #####: acc +6
+coverage: 7 of 9 instructions (77.8%)
+"
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use super::optimizer::remove_instructions;
+use super::{program_dialect, Dialect, Instruction, Limits, Machine, Operand, Status};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    SwapNopJmp,
+    Delete,
+    NegateOperand,
+}
+
+// A single edit that makes the program terminate. The replacement is None
+// when the instruction is deleted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repair {
+    pub pc: usize,
+    pub mutation: Mutation,
+    pub replacement: Option<Instruction>,
+    pub accumulator: i64,
+}
+
+impl Repair {
+    pub fn apply(&self, program: &[Instruction]) -> Vec<Instruction> {
+        match &self.replacement {
+            Some(instruction) => {
+                let mut repaired = program.to_vec();
+                repaired[self.pc] = instruction.clone();
+                repaired
+            }
+            None => delete_instruction(program, self.pc),
+        }
+    }
+}
+
+fn negate(operand: &Operand) -> Option<Operand> {
+    match operand {
+        Operand::Immediate(value) if *value != 0 => value.checked_neg().map(Operand::Immediate),
+        _ => None,
+    }
+}
+
+fn mutate(instruction: &Instruction, mutation: Mutation) -> Option<Option<Instruction>> {
+    match (mutation, instruction) {
+        (Mutation::SwapNopJmp, Instruction::Nop(arg)) => {
+            Some(Some(Instruction::Jmp(*arg as isize)))
+        }
+        (Mutation::SwapNopJmp, Instruction::Jmp(offset)) => {
+            Some(Some(Instruction::Nop(*offset as i64)))
+        }
+        (Mutation::NegateOperand, Instruction::Acc(arg)) if *arg != 0 => {
+            arg.checked_neg().map(|arg| Some(Instruction::Acc(arg)))
+        }
+        (Mutation::NegateOperand, Instruction::Set(register, operand)) => {
+            negate(operand).map(|operand| Some(Instruction::Set(*register, operand)))
+        }
+        (Mutation::NegateOperand, Instruction::Add(register, operand)) => {
+            negate(operand).map(|operand| Some(Instruction::Add(*register, operand)))
+        }
+        (Mutation::NegateOperand, Instruction::Mul(register, operand)) => {
+            negate(operand).map(|operand| Some(Instruction::Mul(*register, operand)))
+        }
+        (Mutation::NegateOperand, Instruction::Out(operand)) => {
+            negate(operand).map(|operand| Some(Instruction::Out(operand)))
+        }
+        (Mutation::NegateOperand, _) => match instruction.jump_offset() {
+            Some(offset) if offset != 0 => offset
+                .checked_neg()
+                .map(|offset| Some(instruction.with_jump_offset(offset))),
+            _ => None,
+        },
+        (Mutation::Delete, _) => Some(None),
+        _ => None,
+    }
+}
+
+// Where execution continues after a version 1 instruction, and what it adds
+// to the accumulator. A deleted instruction behaves like a nop once the
+// jumps around it have been fixed up, and a jump whose target overflows
+// leaves the program like one before the start.
+fn effect(pc: usize, instruction: Option<&Instruction>) -> (isize, i64) {
+    let (offset, delta) = match instruction {
+        Some(Instruction::Acc(arg)) => (1, *arg),
+        Some(Instruction::Jmp(offset)) => (*offset, 0),
+        _ => (1, 0),
+    };
+    ((pc as isize).checked_add(offset).unwrap_or(-1), delta)
+}
+
+fn delete_instruction(program: &[Instruction], deleted: usize) -> Vec<Instruction> {
+    let removed: Vec<bool> = (0..program.len()).map(|pc| pc == deleted).collect();
+    remove_instructions(program, &removed)
+}
+
+// Candidates of programs in the version 2 dialect are executed for at most
+// this many steps, longer runs count as not terminating.
+const EXECUTION_BUDGET: usize = 100_000;
+
+fn find_repairs_by_execution(program: &[Instruction], mutations: &[Mutation]) -> Vec<Repair> {
+    let run = |candidate: &[Instruction]| -> Option<i64> {
+        let limits = Limits {
+            max_steps: Some(EXECUTION_BUDGET),
+            ..Limits::default()
+        };
+        let mut machine = Machine::new(candidate).with_limits(limits);
+        match machine.run_until(|_| false) {
+            Ok(Status::Terminated) => Some(machine.accumulator()),
+            _ => None,
+        }
+    };
+    if run(program).is_some() {
+        return Vec::new();
+    }
+
+    let mut repairs: Vec<Repair> = Vec::new();
+    for (pc, instruction) in program.iter().enumerate() {
+        for mutation in mutations {
+            if let Some(replacement) = mutate(instruction, *mutation) {
+                let mut repair = Repair {
+                    pc,
+                    mutation: *mutation,
+                    replacement,
+                    accumulator: 0,
+                };
+                if let Some(accumulator) = run(&repair.apply(program)) {
+                    repair.accumulator = accumulator;
+                    repairs.push(repair);
+                }
+            }
+        }
+    }
+    repairs
+}
+
+// What the instructions from some pc to the exit add to the accumulator, in
+// total and at the lowest and highest point along the way, so that arriving
+// with a given accumulator can be checked for overflow.
+#[derive(Clone, Copy)]
+struct Suffix {
+    total: i128,
+    lowest: i128,
+    highest: i128,
+}
+
+impl Suffix {
+    const EXIT: Suffix = Suffix {
+        total: 0,
+        lowest: 0,
+        highest: 0,
+    };
+
+    fn prepend(&self, delta: i64) -> Suffix {
+        let delta = delta as i128;
+        Suffix {
+            total: delta + self.total,
+            lowest: (delta + self.lowest).min(0),
+            highest: (delta + self.highest).max(0),
+        }
+    }
+
+    fn accumulator_at_exit(&self, accumulator: i64) -> Option<i64> {
+        let accumulator = accumulator as i128;
+        let fits = accumulator + self.lowest >= i64::MIN as i128
+            && accumulator + self.highest <= i64::MAX as i128;
+        if fits {
+            Some((accumulator + self.total) as i64)
+        } else {
+            None
+        }
+    }
+}
+
+// Returns every single edit, among the allowed mutations, that makes a
+// non-terminating program terminate. Programs that already terminate need
+// no repair and yield no edits.
+//
+// Instead of re-running the program per candidate, the instructions from
+// which the exit is reachable are found by walking the control flow
+// backwards from the exit. Execution only changes at the edited
+// instruction, and up to it follows the original path. So an edit there
+// fixes the program exactly when its new successor reaches the exit
+// without running again an instruction the path ran up to the edit, which
+// would loop, and without the accumulator overflowing on the way.
+// Registers and conditional jumps make the successor depend on more than
+// the pc, so version 2 programs are repaired by running every candidate
+// instead.
+pub fn find_repairs(program: &[Instruction], mutations: &[Mutation]) -> Vec<Repair> {
+    if program_dialect(program) != Dialect::V1 {
+        return find_repairs_by_execution(program, mutations);
+    }
+
+    let exit = program.len();
+    let in_bounds = |target: isize| target >= 0 && target as usize <= exit;
+
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); exit + 1];
+    for (pc, instruction) in program.iter().enumerate() {
+        let (next, _) = effect(pc, Some(instruction));
+        if in_bounds(next) {
+            predecessors[next as usize].push(pc);
+        }
+    }
+
+    // The original path, up to the loop, the invalid jump or the overflow,
+    // with the accumulator before each instruction. step[pc] is when the
+    // path first runs pc.
+    let mut path: Vec<(usize, i64)> = Vec::new();
+    let mut step: Vec<Option<usize>> = vec![None; exit + 1];
+    let mut pc: usize = 0;
+    let mut accumulator: i64 = 0;
+    while pc < exit && step[pc].is_none() {
+        step[pc] = Some(path.len());
+        path.push((pc, accumulator));
+        let (next, delta) = effect(pc, program.get(pc));
+        accumulator = match accumulator.checked_add(delta) {
+            Some(accumulator) => accumulator,
+            None => break,
+        };
+        if !in_bounds(next) {
+            break;
+        }
+        pc = next as usize;
+    }
+
+    // to_exit[pc] is set for every pc that reaches the exit, and
+    // earliest[pc] is the first step of the original path among the
+    // instructions run on the way there.
+    let mut to_exit: Vec<Option<Suffix>> = vec![None; exit + 1];
+    let mut earliest: Vec<usize> = vec![usize::MAX; exit + 1];
+    to_exit[exit] = Some(Suffix::EXIT);
+    let mut queue: Vec<usize> = vec![exit];
+    while let Some(target) = queue.pop() {
+        let suffix = to_exit[target].unwrap();
+        for pc in predecessors[target].iter() {
+            let (_, delta) = effect(*pc, program.get(*pc));
+            to_exit[*pc] = Some(suffix.prepend(delta));
+            earliest[*pc] = earliest[target].min(step[*pc].unwrap_or(usize::MAX));
+            queue.push(*pc);
+        }
+    }
+
+    if let Some(Some(_)) = to_exit[0].map(|suffix| suffix.accumulator_at_exit(0)) {
+        return Vec::new();
+    }
+
+    path.sort_unstable();
+
+    let mut repairs: Vec<Repair> = Vec::new();
+    for (pc, accumulator) in path {
+        for mutation in mutations {
+            let replacement = match mutate(&program[pc], *mutation) {
+                Some(replacement) => replacement,
+                None => continue,
+            };
+            let (next, delta) = effect(pc, replacement.as_ref());
+            // Running into an instruction the path ran up to the edit,
+            // the edited one included, loops again.
+            if !in_bounds(next) || Some(earliest[next as usize]) <= step[pc] {
+                continue;
+            }
+            let at_exit = accumulator.checked_add(delta).and_then(|accumulator| {
+                to_exit[next as usize].and_then(|suffix| suffix.accumulator_at_exit(accumulator))
+            });
+            if let Some(accumulator) = at_exit {
+                repairs.push(Repair {
+                    pc,
+                    mutation: *mutation,
+                    replacement,
+                    accumulator,
+                });
+            }
+        }
+    }
+    repairs
+}
+
+#[cfg(test)]
+mod repair_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+    use crate::execute_program;
+
+    const ALL: [Mutation; 3] = [
+        Mutation::SwapNopJmp,
+        Mutation::Delete,
+        Mutation::NegateOperand,
+    ];
+
+    #[test]
+    fn test_swap() {
+        let repairs = find_repairs(&EXAMPLE1, &[Mutation::SwapNopJmp]);
+        assert_eq!(
+            repairs,
+            vec![Repair {
+                pc: 7,
+                mutation: Mutation::SwapNopJmp,
+                replacement: Some(Instruction::Nop(-4)),
+                accumulator: 8,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_all_mutations() {
+        let repairs = find_repairs(&EXAMPLE1, &ALL);
+        let found: Vec<(usize, Mutation, i64)> = repairs
+            .iter()
+            .map(|repair| (repair.pc, repair.mutation, repair.accumulator))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(7, Mutation::SwapNopJmp, 8), (7, Mutation::Delete, 8)]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        for repair in find_repairs(&EXAMPLE1, &ALL) {
+            let repaired = repair.apply(&EXAMPLE1);
+            assert_eq!(execute_program(&repaired).ok(), Some(repair.accumulator));
+        }
+    }
+
+    #[test]
+    fn test_delete_fixes_jumps() {
+        let program = [
+            Instruction::Jmp(3),
+            Instruction::Acc(1),
+            Instruction::Nop(-1),
+            Instruction::Jmp(-2),
+        ];
+        assert_eq!(
+            delete_instruction(&program, 1),
+            vec![
+                Instruction::Jmp(2),
+                Instruction::Nop(0),
+                Instruction::Jmp(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_terminating() {
+        let program = [Instruction::Acc(1), Instruction::Jmp(1)];
+        assert_eq!(find_repairs(&program, &ALL), vec![]);
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let program = crate::parse_program(
+            "nop +2\nacc +5\njmp +3\nacc -2\njmp -4\njmp -2\nacc +7\njmp -6\nacc +1\n",
+        )
+        .unwrap();
+        let repairs = find_repairs(&program, &ALL);
+        assert!(!repairs.is_empty());
+        for pc in 0..program.len() {
+            for mutation in ALL.iter() {
+                let replacement = match mutate(&program[pc], *mutation) {
+                    Some(replacement) => replacement,
+                    None => continue,
+                };
+                let repair = Repair {
+                    pc,
+                    mutation: *mutation,
+                    replacement,
+                    accumulator: 0,
+                };
+                let result = execute_program(&repair.apply(&program)).ok();
+                let found = repairs
+                    .iter()
+                    .find(|r| r.pc == pc && r.mutation == *mutation)
+                    .map(|r| r.accumulator);
+                assert_eq!(found, result, "{} {:?}", pc, mutation);
+            }
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = [
+            Instruction::Acc(i64::MAX),
+            Instruction::Acc(1),
+            Instruction::Acc(-1),
+        ];
+        assert!(execute_program(&program).is_err());
+        let found: Vec<(usize, Mutation, i64)> = find_repairs(&program, &ALL)
+            .iter()
+            .map(|repair| (repair.pc, repair.mutation, repair.accumulator))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, Mutation::Delete, 0),
+                (0, Mutation::NegateOperand, -i64::MAX),
+                (1, Mutation::Delete, i64::MAX - 1),
+                (1, Mutation::NegateOperand, i64::MAX - 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_overflow() {
+        let program = [Instruction::Nop(0), Instruction::Jmp(isize::MAX)];
+        let repairs = find_repairs(&program, &ALL);
+        assert!(!repairs.is_empty());
+        assert!(repairs.iter().all(|repair| repair.pc == 1));
+        assert!(repairs
+            .iter()
+            .any(|repair| repair.mutation == Mutation::SwapNopJmp && repair.accumulator == 0));
+    }
+
+    #[test]
+    fn test_overflow_path_reaches_exit() {
+        // Every instruction reaches the exit, but jumping back to the start
+        // runs the edited one again.
+        let program = [
+            Instruction::Acc(-10),
+            Instruction::Nop(-1),
+            Instruction::Acc(i64::MAX),
+            Instruction::Acc(11),
+        ];
+        assert!(execute_program(&program).is_err());
+        assert!(find_repairs(&program, &[Mutation::SwapNopJmp]).is_empty());
+        let repairs = find_repairs(&program, &ALL);
+        assert!(!repairs.is_empty());
+        for repair in repairs.iter() {
+            assert_eq!(
+                execute_program(&repair.apply(&program)),
+                Ok(repair.accumulator)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dialect2() {
+        let program =
+            crate::parse_program(".dialect 2\nset a +3\nloop: add acc a\nadd a +1\njnz a loop\n")
+                .unwrap();
+        let repairs = find_repairs(&program, &ALL);
+        let found: Vec<(usize, Mutation, i64)> = repairs
+            .iter()
+            .map(|repair| (repair.pc, repair.mutation, repair.accumulator))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, Mutation::NegateOperand, -3 - 2 - 1),
+                (2, Mutation::NegateOperand, 3 + 2 + 1),
+                (3, Mutation::Delete, 3),
+            ]
+        );
+    }
+}
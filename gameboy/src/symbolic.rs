@@ -0,0 +1,383 @@
+use super::Instruction;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+// Symbolic evaluation of version 1 programs over single flips, a flip being
+// a nop swapped for a jmp or the other way around. The variable f<pc> is 1
+// when the instruction at pc is flipped, and at most one variable is 1.
+//
+// Only flips the original run executes can change anything, and a flip only
+// matters from the moment it is executed. The original run is evaluated once
+// and every flip continues from where the original run reaches it, so the
+// shared prefix is never run again. In the worst case each flip still runs
+// through most of the program, so the evaluation is quadratic in the length
+// of the original run.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolicError {
+    // Only nop, acc and jmp can be evaluated.
+    Unsupported(usize, Instruction),
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Unsupported(pc, instruction) => {
+                write!(f, "{}: cannot evaluate {} symbolically", pc, instruction)
+            }
+        }
+    }
+}
+
+impl error::Error for SymbolicError {}
+
+// constant + the coefficient of the flipped instruction, if any.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Affine {
+    pub constant: i128,
+    pub coefficients: BTreeMap<usize, i128>,
+}
+
+impl Affine {
+    pub fn evaluate(&self, flip: Option<usize>) -> i128 {
+        let coefficient = flip
+            .and_then(|pc| self.coefficients.get(&pc))
+            .copied()
+            .unwrap_or(0);
+        self.constant + coefficient
+    }
+}
+
+impl fmt::Display for Affine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.constant)?;
+        for (pc, coefficient) in self.coefficients.iter() {
+            let sign = if *coefficient < 0 { '-' } else { '+' };
+            write!(f, " {} {}*f{}", sign, coefficient.abs(), pc)?;
+        }
+        Ok(())
+    }
+}
+
+// Either the original run gets here, as long as none of the flips it
+// executed on the way is flipped, or one of the flips does.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathCondition {
+    pub original: Option<Vec<usize>>,
+    pub flips: Vec<usize>,
+}
+
+impl fmt::Display for PathCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut terms: Vec<String> = Vec::new();
+        match &self.original {
+            Some(unflipped) if unflipped.is_empty() => terms.push(String::from("true")),
+            Some(unflipped) => terms.push(
+                unflipped
+                    .iter()
+                    .map(|pc| format!("!f{}", pc))
+                    .collect::<Vec<String>>()
+                    .join(" & "),
+            ),
+            None => (),
+        }
+        terms.extend(self.flips.iter().map(|pc| format!("f{}", pc)));
+        if terms.is_empty() {
+            write!(f, "false")
+        } else {
+            write!(f, "{}", terms.join(" | "))
+        }
+    }
+}
+
+pub struct SymbolicEvaluation {
+    // The step at which the original run reaches each pc, and its
+    // accumulator there. The exit is the last entry.
+    original: Vec<Option<(usize, i64)>>,
+    // The flips executed by the original run, in order.
+    candidates: Vec<usize>,
+    // Accumulators of the runs that got somewhere by a different route
+    // than the original run, by flip.
+    flipped: Vec<BTreeMap<usize, i64>>,
+}
+
+fn flip(instruction: &Instruction) -> Option<Instruction> {
+    match instruction {
+        Instruction::Nop(arg) => Some(Instruction::Jmp(*arg as isize)),
+        Instruction::Jmp(offset) => Some(Instruction::Nop(*offset as i64)),
+        _ => None,
+    }
+}
+
+// A jump whose target does not fit in an isize is out of bounds, and
+// reported as -1.
+fn effect(pc: usize, instruction: &Instruction) -> (isize, i64) {
+    let (offset, delta) = match instruction {
+        Instruction::Acc(arg) => (1, *arg),
+        Instruction::Jmp(offset) => (*offset, 0),
+        _ => (1, 0),
+    };
+    ((pc as isize).checked_add(offset).unwrap_or(-1), delta)
+}
+
+pub fn evaluate(program: &[Instruction]) -> Result<SymbolicEvaluation, SymbolicError> {
+    for (pc, instruction) in program.iter().enumerate() {
+        if let Instruction::Nop(_) | Instruction::Acc(_) | Instruction::Jmp(_) = instruction {
+            continue;
+        }
+        return Err(SymbolicError::Unsupported(pc, instruction.clone()));
+    }
+
+    let exit = program.len();
+    let in_bounds = |target: isize| target >= 0 && target as usize <= exit;
+    let mut evaluation = SymbolicEvaluation {
+        original: vec![None; exit + 1],
+        candidates: Vec::new(),
+        flipped: vec![BTreeMap::new(); exit + 1],
+    };
+
+    // The original run, stopping at a loop, a fault or an overflow.
+    let mut path: Vec<(usize, i64)> = Vec::new();
+    let mut pc: usize = 0;
+    let mut accumulator: i64 = 0;
+    loop {
+        if evaluation.original[pc].is_some() {
+            break;
+        }
+        evaluation.original[pc] = Some((path.len(), accumulator));
+        if pc == exit {
+            break;
+        }
+        path.push((pc, accumulator));
+        let (next, delta) = effect(pc, &program[pc]);
+        accumulator = match accumulator.checked_add(delta) {
+            Some(accumulator) => accumulator,
+            None => break,
+        };
+        if !in_bounds(next) {
+            break;
+        }
+        pc = next as usize;
+    }
+
+    // Every flip continues from where the original run executes it. Going
+    // back to any instruction the original run executed up to the flip is a
+    // loop, just like going back to one of its own.
+    let mut visited_by: Vec<usize> = vec![usize::MAX; exit];
+    for (step, (flip_pc, accumulator)) in path.iter().enumerate() {
+        let flipped = match flip(&program[*flip_pc]) {
+            Some(flipped) => flipped,
+            None => continue,
+        };
+        evaluation.candidates.push(*flip_pc);
+
+        let (mut next, _) = effect(*flip_pc, &flipped);
+        let mut accumulator = *accumulator;
+        while in_bounds(next) {
+            let pc = next as usize;
+            let before_flip = matches!(evaluation.original[pc], Some((at, _)) if at <= step);
+            if pc < exit && (before_flip || visited_by[pc] == step) {
+                break;
+            }
+            evaluation.flipped[pc].insert(*flip_pc, accumulator);
+            if pc == exit {
+                break;
+            }
+            visited_by[pc] = step;
+            let (target, delta) = effect(pc, &program[pc]);
+            accumulator = match accumulator.checked_add(delta) {
+                Some(accumulator) => accumulator,
+                None => break,
+            };
+            next = target;
+        }
+    }
+    Ok(evaluation)
+}
+
+impl SymbolicEvaluation {
+    pub fn exit(&self) -> usize {
+        self.original.len() - 1
+    }
+
+    // The flips the original run executes, in the order it executes them.
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+
+    // The accumulator when pc is first reached with the given flip, if it is
+    // reached at all.
+    pub fn accumulator_at(&self, pc: usize, flip: Option<usize>) -> Option<i64> {
+        if let Some(accumulator) = flip.and_then(|flip| self.flipped[pc].get(&flip)) {
+            return Some(*accumulator);
+        }
+        let (step, accumulator) = self.original[pc]?;
+        match flip.and_then(|flip| self.original[flip]) {
+            // Flipped before getting here, and the flipped run did not.
+            Some((flip_step, _)) if flip_step < step => None,
+            _ => Some(accumulator),
+        }
+    }
+
+    pub fn accumulator(&self, pc: usize) -> Option<Affine> {
+        let original = self.original[pc].map(|(_, accumulator)| accumulator);
+        if original.is_none() && self.flipped[pc].is_empty() {
+            return None;
+        }
+        let constant = original.unwrap_or(0) as i128;
+        let coefficients = self.flipped[pc]
+            .iter()
+            .map(|(flip, accumulator)| (*flip, *accumulator as i128 - constant))
+            .filter(|(_, coefficient)| *coefficient != 0)
+            .collect();
+        Some(Affine {
+            constant,
+            coefficients,
+        })
+    }
+
+    pub fn condition(&self, pc: usize) -> PathCondition {
+        let original = self.original[pc].map(|(step, _)| {
+            self.candidates
+                .iter()
+                .copied()
+                .filter(|flip| matches!(self.original[*flip], Some((at, _)) if at < step))
+                .collect()
+        });
+        PathCondition {
+            original,
+            flips: self.flipped[pc].keys().copied().collect(),
+        }
+    }
+
+    // The flips that make the program terminate with the given accumulator,
+    // None standing for no flip at all. Flipping an instruction that never
+    // runs changes nothing and is not listed.
+    pub fn flips_ending_with(&self, accumulator: i64) -> Vec<Option<usize>> {
+        let exit = self.exit();
+        let mut flips: Vec<Option<usize>> = Vec::new();
+        if let Some((_, original)) = self.original[exit] {
+            if original == accumulator {
+                flips.push(None);
+            }
+        }
+        flips.extend(
+            self.flipped[exit]
+                .iter()
+                .filter(|(_, at_exit)| **at_exit == accumulator)
+                .map(|(flip, _)| Some(*flip)),
+        );
+        flips
+    }
+}
+
+#[cfg(test)]
+mod symbolic_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+    use crate::generate::{generate, GeneratorConfig, Rng};
+    use crate::trace::{Event, Tracer};
+    use crate::{execute_program_with_tracer, Register};
+
+    #[test]
+    fn test_example() {
+        let evaluation = evaluate(&EXAMPLE1).unwrap();
+        assert_eq!(evaluation.candidates(), &[0, 2, 7, 4]);
+        assert_eq!(evaluation.flips_ending_with(8), vec![Some(7)]);
+        assert_eq!(evaluation.flips_ending_with(5), vec![]);
+
+        let at_exit = evaluation.accumulator(9).unwrap();
+        assert_eq!(at_exit.to_string(), "0 + 8*f7");
+        assert_eq!(evaluation.condition(9).to_string(), "f7");
+
+        assert_eq!(evaluation.accumulator(6).unwrap().to_string(), "1");
+        assert_eq!(evaluation.condition(6).to_string(), "!f0 & !f2");
+        assert_eq!(evaluation.accumulator(3).unwrap().to_string(), "2 - 1*f2");
+        assert_eq!(evaluation.condition(3).to_string(), "!f0 & !f2 & !f7 | f2");
+        assert_eq!(evaluation.accumulator_at(3, Some(2)), Some(1));
+        assert_eq!(evaluation.accumulator_at(3, Some(4)), Some(2));
+        assert_eq!(evaluation.accumulator_at(6, Some(0)), None);
+        assert_eq!(evaluation.condition(0).to_string(), "true");
+        assert_eq!(evaluation.condition(5).to_string(), "f4");
+        assert_eq!(evaluation.accumulator(5).unwrap().to_string(), "0 + 5*f4");
+    }
+
+    #[test]
+    fn test_jump_overflow() {
+        let program = [Instruction::Acc(3), Instruction::Jmp(isize::MAX)];
+        let evaluation = evaluate(&program).unwrap();
+        assert_eq!(evaluation.flips_ending_with(3), vec![Some(1)]);
+        let program = [Instruction::Acc(3), Instruction::Nop(i64::MAX)];
+        let evaluation = evaluate(&program).unwrap();
+        assert_eq!(evaluation.flips_ending_with(3), vec![None]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let program = [Instruction::Nop(0), Instruction::Inp(Register::A)];
+        assert!(matches!(
+            evaluate(&program),
+            Err(SymbolicError::Unsupported(1, _))
+        ));
+    }
+
+    // Records the accumulator when each pc is first fetched.
+    struct FirstVisit(BTreeMap<usize, i64>, i64);
+
+    impl Tracer for FirstVisit {
+        fn trace(&mut self, event: &Event) {
+            match event {
+                Event::Fetch { pc, .. } => {
+                    self.0.entry(*pc).or_insert(self.1);
+                }
+                Event::AccumulatorChanged { new, .. } => self.1 = *new,
+                Event::Terminated { accumulator } => {
+                    self.0.insert(usize::MAX, *accumulator);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_execution() {
+        let mut rng = Rng::new(16);
+        for len in 1..30 {
+            let config = GeneratorConfig {
+                len,
+                ..GeneratorConfig::default()
+            };
+            let program = generate(&config, &mut rng);
+            let evaluation = evaluate(&program).unwrap();
+            let mut flips: Vec<Option<usize>> = vec![None];
+            flips.extend((0..len).map(Some));
+            for flip_pc in flips {
+                let mut variant = program.clone();
+                if let Some(flip_pc) = flip_pc {
+                    match flip(&program[flip_pc]) {
+                        Some(flipped) => variant[flip_pc] = flipped,
+                        None => continue,
+                    }
+                }
+                let mut first_visit = FirstVisit(BTreeMap::new(), 0);
+                let result = execute_program_with_tracer(&variant, &mut first_visit);
+                for pc in 0..len {
+                    assert_eq!(
+                        evaluation.accumulator_at(pc, flip_pc),
+                        first_visit.0.get(&pc).copied(),
+                        "{:?} flip {:?} at {}",
+                        program,
+                        flip_pc,
+                        pc
+                    );
+                    if let Some(accumulator) = evaluation.accumulator_at(pc, flip_pc) {
+                        let affine = evaluation.accumulator(pc).unwrap();
+                        assert_eq!(affine.evaluate(flip_pc), accumulator as i128);
+                    }
+                }
+                assert_eq!(evaluation.accumulator_at(len, flip_pc), result.ok());
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use super::{ExitError, Instruction, Register};
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<'a> {
+    Fetch {
+        pc: usize,
+        instruction: &'a Instruction,
+    },
+    AccumulatorChanged {
+        pc: usize,
+        old: i64,
+        new: i64,
+    },
+    RegisterChanged {
+        pc: usize,
+        register: Register,
+        old: i64,
+        new: i64,
+    },
+    Output {
+        pc: usize,
+        value: i64,
+    },
+    Jump {
+        from: usize,
+        to: usize,
+    },
+    LoopDetected {
+        pc: usize,
+        accumulator: i64,
+    },
+    // The fetched instruction failed, and the step with it.
+    Fault {
+        pc: usize,
+        error: &'a ExitError,
+    },
+    Terminated {
+        accumulator: i64,
+    },
+}
+
+impl<'a> fmt::Display for Event<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Fetch { pc, instruction } => write!(f, "[{}] {}", pc, instruction),
+            Event::AccumulatorChanged { pc, old, new } => {
+                write!(f, "[{}] acc {} -> {}", pc, old, new)
+            }
+            Event::RegisterChanged {
+                pc,
+                register,
+                old,
+                new,
+            } => write!(f, "[{}] {} {} -> {}", pc, register, old, new),
+            Event::Output { pc, value } => write!(f, "[{}] out {}", pc, value),
+            Event::Jump { from, to } => write!(f, "[{}] jump to {}", from, to),
+            Event::LoopDetected { pc, accumulator } => {
+                write!(f, "[{}] loop detected, acc {}", pc, accumulator)
+            }
+            Event::Fault { pc, error } => write!(f, "[{}] fault: {}", pc, error),
+            Event::Terminated { accumulator } => write!(f, "terminated, acc {}", accumulator),
+        }
+    }
+}
+
+impl<'a> Event<'a> {
+    pub fn to_json(&self) -> String {
+        match self {
+            Event::Fetch { pc, instruction } => format!(
+                r#"{{"event":"fetch","pc":{},"instruction":"{}"}}"#,
+                pc, instruction
+            ),
+            Event::AccumulatorChanged { pc, old, new } => format!(
+                r#"{{"event":"accumulator","pc":{},"old":{},"new":{}}}"#,
+                pc, old, new
+            ),
+            Event::RegisterChanged {
+                pc,
+                register,
+                old,
+                new,
+            } => format!(
+                r#"{{"event":"register","pc":{},"register":"{}","old":{},"new":{}}}"#,
+                pc, register, old, new
+            ),
+            Event::Output { pc, value } => {
+                format!(r#"{{"event":"output","pc":{},"value":{}}}"#, pc, value)
+            }
+            Event::Jump { from, to } => {
+                format!(r#"{{"event":"jump","from":{},"to":{}}}"#, from, to)
+            }
+            Event::LoopDetected { pc, accumulator } => format!(
+                r#"{{"event":"loop","pc":{},"accumulator":{}}}"#,
+                pc, accumulator
+            ),
+            Event::Fault { pc, error } => {
+                format!(r#"{{"event":"fault","pc":{},"error":"{}"}}"#, pc, error)
+            }
+            Event::Terminated { accumulator } => {
+                format!(r#"{{"event":"terminated","accumulator":{}}}"#, accumulator)
+            }
+        }
+    }
+}
+
+pub trait Tracer {
+    fn trace(&mut self, event: &Event);
+}
+
+impl<T: Tracer + ?Sized> Tracer for &mut T {
+    fn trace(&mut self, event: &Event) {
+        (**self).trace(event)
+    }
+}
+
+pub struct NoopTracer;
+
+impl Tracer for NoopTracer {
+    fn trace(&mut self, _event: &Event) {}
+}
+
+pub struct StdoutTracer;
+
+impl Tracer for StdoutTracer {
+    fn trace(&mut self, event: &Event) {
+        println!("{}", event);
+    }
+}
+
+// Writes one JSON object per event. The first write error stops the
+// tracing and is reported by into_inner.
+pub struct JsonLinesTracer<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Tracer for JsonLinesTracer<W> {
+    fn trace(&mut self, event: &Event) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", event.to_json()) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    use crate::examples::EXAMPLE1;
+    use crate::{execute_program_with_tracer, ExitError};
+
+    struct Collect(Vec<String>);
+
+    impl Tracer for Collect {
+        fn trace(&mut self, event: &Event) {
+            self.0.push(event.to_string());
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let mut collect = Collect(Vec::new());
+        let program = [
+            Instruction::Nop(0),
+            Instruction::Acc(1),
+            Instruction::Jmp(2),
+            Instruction::Acc(3),
+        ];
+        let result = execute_program_with_tracer(&program, &mut collect);
+        assert!(matches!(result, Ok(1)));
+        assert_eq!(
+            collect.0,
+            vec![
+                "[0] nop +0",
+                "[1] acc +1",
+                "[1] acc 0 -> 1",
+                "[2] jmp +2",
+                "[2] jump to 4",
+                "terminated, acc 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut collect = Collect(Vec::new());
+        let program = [Instruction::Acc(i64::MAX), Instruction::Acc(1)];
+        let result = execute_program_with_tracer(&program, &mut collect);
+        assert!(matches!(result, Err(ExitError::ArithmeticOverflow(1))));
+        assert_eq!(
+            &collect.0[2..],
+            &["[1] acc +1", "[1] fault: arithmetic overflow at 1"]
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut tracer = JsonLinesTracer::new(Vec::new());
+        let result = execute_program_with_tracer(&EXAMPLE1, &mut tracer);
+        assert!(matches!(result, Err(ExitError::InfiniteLoop(5, 1))));
+        let output = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 7 + 3 + 3 + 1);
+        assert_eq!(
+            lines[0],
+            r#"{"event":"fetch","pc":0,"instruction":"nop +0"}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"event":"accumulator","pc":1,"old":0,"new":1}"#
+        );
+        assert_eq!(lines[4], r#"{"event":"jump","from":2,"to":6}"#);
+        assert_eq!(
+            lines.last().unwrap(),
+            &r#"{"event":"loop","pc":1,"accumulator":5}"#
+        );
+    }
+}